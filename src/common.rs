@@ -1,3 +1,7 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul};
+
 pub struct Answer {
     pub a: u64,
     pub b: u64,
@@ -20,3 +24,233 @@ macro_rules! answer {
         }
     };
 }
+
+/* Command-line options like `--name=value` shared with days */
+
+static OPTIONS: std::sync::OnceLock<std::collections::HashMap<String, String>> =
+    std::sync::OnceLock::new();
+
+/// Store options parsed from the command line, can be set only once
+pub fn set_options(options: std::collections::HashMap<String, String>) {
+    OPTIONS.set(options).expect("options already set");
+}
+
+/// Value of option `--name=value`, empty string for a bare `--name` flag
+pub fn option(name: &str) -> Option<&'static str> {
+    OPTIONS.get()?.get(name).map(|s| s.as_str())
+}
+
+/* Big numbers shared by days whose answers outgrow u64 */
+
+/// Arbitrary-precision unsigned integer with little-endian 64-bit limbs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigUint {
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+    pub fn from_u64(n: u64) -> BigUint {
+        let mut big = BigUint { limbs: vec![n] };
+        big.trim();
+        big
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs.len() {
+            0 => Some(0),
+            1 => Some(self.limbs[0]),
+            _ => None,
+        }
+    }
+
+    /// Parse a string of binary digits, most significant first
+    pub fn from_binary(s: &str) -> Option<BigUint> {
+        if s.is_empty() || !s.bytes().all(|b| b == b'0' || b == b'1') {
+            return None;
+        }
+        let mut big = BigUint { limbs: vec![] };
+        for (pos, b) in s.bytes().rev().enumerate() {
+            if b == b'1' {
+                big.set_bit(pos);
+            }
+        }
+        Some(big)
+    }
+
+    pub fn bit(&self, pos: usize) -> bool {
+        self.limbs
+            .get(pos / 64)
+            .is_some_and(|limb| (limb >> (pos % 64)) & 1 == 1)
+    }
+
+    pub fn set_bit(&mut self, pos: usize) {
+        if self.limbs.len() <= pos / 64 {
+            self.limbs.resize(pos / 64 + 1, 0);
+        }
+        self.limbs[pos / 64] |= 1 << (pos % 64);
+    }
+
+    /// Divide in place by a small divisor and return the remainder
+    fn div_rem_small(&mut self, divisor: u64) -> u64 {
+        let mut rem: u128 = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let cur = (rem << 64) | *limb as u128;
+            *limb = (cur / divisor as u128) as u64;
+            rem = cur % divisor as u128;
+        }
+        self.trim();
+        rem as u64
+    }
+}
+
+// limbs are trimmed, so a longer number is always larger
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigUint {
+    type Output = BigUint;
+
+    fn add(self, other: &BigUint) -> BigUint {
+        let (long, short) = if self.limbs.len() >= other.limbs.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut limbs = Vec::with_capacity(long.limbs.len() + 1);
+        let mut carry = false;
+        for (i, a) in long.limbs.iter().enumerate() {
+            let b = short.limbs.get(i).copied().unwrap_or(0);
+            let (sum, c1) = a.overflowing_add(b);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            limbs.push(sum);
+            carry = c1 || c2;
+        }
+        if carry {
+            limbs.push(1);
+        }
+        BigUint { limbs }
+    }
+}
+
+impl Mul for &BigUint {
+    type Output = BigUint;
+
+    fn mul(self, other: &BigUint) -> BigUint {
+        if self.limbs.is_empty() || other.limbs.is_empty() {
+            return BigUint { limbs: vec![] };
+        }
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            if *a == 0 {
+                continue;
+            }
+            let mut carry: u128 = 0;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let cur = *a as u128 * *b as u128 + limbs[i + j] as u128 + carry;
+                limbs[i + j] = cur as u64;
+                carry = cur >> 64;
+            }
+            limbs[i + other.limbs.len()] = carry as u64;
+        }
+        let mut big = BigUint { limbs };
+        big.trim();
+        big
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut n = self.clone();
+        let mut chunks = vec![];
+        while !n.limbs.is_empty() {
+            chunks.push(n.div_rem_small(CHUNK));
+        }
+        match chunks.pop() {
+            None => write!(f, "0"),
+            Some(top) => {
+                write!(f, "{}", top)?;
+                for chunk in chunks.iter().rev() {
+                    write!(f, "{:019}", chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Binary for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits: String = match self.limbs.split_last() {
+            None => "0".to_string(),
+            Some((top, rest)) => {
+                let mut digits = format!("{:b}", top);
+                for limb in rest.iter().rev() {
+                    digits += &format!("{:064b}", limb);
+                }
+                digits
+            }
+        };
+        f.pad_integral(true, "0b", &digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_big_uint() {
+        let a = BigUint::from_u64(u64::MAX);
+        let square = &a * &a;
+        assert_eq!(
+            square.to_string(),
+            "340282366920938463426481119284349108225"
+        );
+        assert_eq!(
+            (&square + &a).to_string(),
+            "340282366920938463444927863358058659840"
+        );
+        assert_eq!(BigUint::from_u64(0).to_string(), "0");
+    }
+
+    #[test]
+    fn test_big_uint_bits() {
+        let wide = "1".to_string() + &"0".repeat(99) + "101";
+        let big = BigUint::from_binary(&wide).unwrap();
+        assert!(big.bit(0) && !big.bit(1) && big.bit(2) && big.bit(102));
+        assert!(!big.bit(103) && !big.bit(1000));
+        assert_eq!(format!("{:b}", big), wide);
+        assert_eq!(format!("{:08b}", BigUint::from_u64(5)), "00000101");
+        assert_eq!(format!("{:b}", BigUint::from_u64(0)), "0");
+        assert_eq!(BigUint::from_binary("000101"), Some(BigUint::from_u64(5)));
+        assert_eq!(BigUint::from_binary("0012"), None);
+        assert_eq!(BigUint::from_binary(""), None);
+
+        let mut values: Vec<BigUint> = ["0", "1", "10", &wide, &wide[..70], "000"]
+            .iter()
+            .map(|s| BigUint::from_binary(s).unwrap())
+            .collect();
+        values.sort();
+        let sorted: Vec<String> = values.iter().map(|v| format!("{:b}", v)).collect();
+        assert_eq!(sorted, ["0", "0", "1", "10", &wide[..70], &wide]);
+    }
+}
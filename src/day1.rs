@@ -1,35 +1,127 @@
-use crate::{answer, common::Answer};
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(7, 5);
 
-fn read_numbers(lines: Vec<String>) -> Vec<u64> {
-    lines.iter().map(|s| s.parse().unwrap()).collect()
+/// Counts sums of sliding windows that are larger than the previous window.
+/// Consecutive windows share all but one value, so comparing the value that
+/// enters with the one that leaves is enough and only `window` values are kept.
+struct SweepCounter {
+    window: usize,
+    recent: VecDeque<u64>,
+    seen: usize,
+    count: u64,
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
-    let numbers = read_numbers(lines);
-    let len = numbers.len();
-    let mut count = 1;
-    for i in 2..len {
-        if numbers[i] > numbers[i - 1] {
-            count += 1
+impl SweepCounter {
+    fn new(window: usize) -> SweepCounter {
+        assert!(window > 0, "Window must not be empty");
+        SweepCounter {
+            window,
+            recent: VecDeque::with_capacity(window + 1),
+            seen: 0,
+            count: 0,
+        }
+    }
+
+    /// Add a measurement, returns the start index of the window if it increased
+    fn push(&mut self, value: u64) -> Option<usize> {
+        self.recent.push_back(value);
+        self.seen += 1;
+        if self.recent.len() <= self.window {
+            return None;
+        }
+        let left = self.recent.pop_front().unwrap();
+        if value > left {
+            self.count += 1;
+            Some(self.seen - self.window)
+        } else {
+            None
         }
     }
-    count
+}
+
+#[derive(Debug, PartialEq)]
+struct Increases {
+    count: u64,
+    positions: Vec<usize>,
+}
+
+fn count_increases(numbers: impl Iterator<Item = u64>, window: usize) -> Increases {
+    let mut counter = SweepCounter::new(window);
+    let positions = numbers.filter_map(|n| counter.push(n)).collect();
+    Increases {
+        count: counter.count,
+        positions,
+    }
+}
+
+fn parse_number(line: &str) -> Option<u64> {
+    let line = line.trim();
+    (!line.is_empty()).then(|| line.parse().expect("Bad measurement"))
+}
+
+/// Count increases on stdin line by line, printing positions as they are found
+fn count_increases_stdin(window: usize) -> u64 {
+    let mut counter = SweepCounter::new(window);
+    for line in std::io::stdin().lock().lines() {
+        if let Some(pos) =
+            parse_number(&line.expect("Cannot read stdin")).and_then(|n| counter.push(n))
+        {
+            println!("increase at {}", pos);
+        }
+    }
+    counter.count
+}
+
+fn sweep(lines: Vec<String>, default_window: usize) -> u64 {
+    let window =
+        common::option("window").map_or(default_window, |w| w.parse().expect("Wrong window"));
+    if common::option("stream").is_some() {
+        return count_increases_stdin(window);
+    }
+    let increases = count_increases(lines.iter().filter_map(|l| parse_number(l)), window);
+    println!(
+        "Increases of {}-wide windows at {:?}",
+        window, increases.positions
+    );
+    increases.count
+}
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    sweep(lines, 1)
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let numbers = read_numbers(lines);
-    let len = numbers.len();
-    let mut count = 0;
-    let mut prev_window = u64::max_value();
-    for i in 0..len - 2 {
-        let window = numbers[i] + numbers[i + 1] + numbers[i + 2];
-        println!("at {} win {}", i, window);
-        if window > prev_window {
-            count += 1
+    sweep(lines, 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SAMPLE: [u64; 10] = [199, 200, 208, 210, 200, 207, 240, 269, 260, 263];
+
+    #[test]
+    fn test_sample() {
+        let a = count_increases(SAMPLE.into_iter(), 1);
+        assert_eq!(a.count, 7);
+        assert_eq!(a.positions, vec![1, 2, 3, 5, 6, 7, 9]);
+        let b = count_increases(SAMPLE.into_iter(), 3);
+        assert_eq!(b.count, 5);
+        assert_eq!(b.positions, vec![1, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_window_sizes() {
+        for window in 1..=12 {
+            let sums: Vec<u64> = SAMPLE.windows(window).map(|w| w.iter().sum()).collect();
+            let expected: Vec<usize> = (1..sums.len()).filter(|i| sums[*i] > sums[i - 1]).collect();
+            let increases = count_increases(SAMPLE.into_iter(), window);
+            assert_eq!(increases.positions, expected);
         }
-        prev_window = window;
+        assert_eq!(count_increases(std::iter::empty(), 3).count, 0);
     }
-    count
 }
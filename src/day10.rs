@@ -1,129 +1,233 @@
-use std::collections::HashMap;
+use std::fmt;
 
-use crate::{answer, common::Answer};
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(26397, 288957);
 
 /* Impl */
 
-fn pairs() -> HashMap<char, char> {
-    HashMap::from([('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
-}
+// puzzle brackets with their syntax error and completion scores
+const DEFAULT_PAIRS: &str = "()=3:1,[]=57:2,{}=1197:3,<>=25137:4";
 
-//fn revpairs() -> HashMap<char, char> {
-//let r: Vec<(char, char)> = pairs().iter().map(|(k,v)| (*v,*k)).collect();
-//HashMap::from_iter(r)
-//}
+/// Line and column, both starting at 1
+type Position = (usize, usize);
 
-fn score_a(c: char) -> u32 {
-    match c {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
-        _ => panic!("bad symbol for score {}", c),
-    }
+/// Closing character that does not match the innermost open bracket
+#[derive(Debug, PartialEq)]
+struct SyntaxError {
+    pos: Position,
+    /// closer of the innermost open bracket, none when nothing is open
+    expected: Option<char>,
+    found: char,
 }
 
-fn score_b(c: char) -> u32 {
-    match c {
-        ')' => 1,
-        ']' => 2,
-        '}' => 3,
-        '>' => 4,
-        _ => panic!("bad symbol for score {}", c),
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expected {
+            Some(expected) => write!(
+                f,
+                "{}:{}: expected {}, but found {} instead",
+                self.pos.0, self.pos.1, expected, self.found
+            ),
+            None => write!(
+                f,
+                "{}:{}: unexpected {}, nothing is open",
+                self.pos.0, self.pos.1, self.found
+            ),
+        }
     }
 }
 
-fn handle_open(stack: &mut Vec<char>, c: char) -> Result<(), char> {
-    stack.push(c);
-    Ok(())
+#[derive(Debug, Default, PartialEq)]
+struct CheckResult {
+    errors: Vec<SyntaxError>,
+    /// brackets left open at the end, outermost first
+    unclosed: Vec<(Position, char)>,
+}
+
+/// Bracket pair with optional syntax error and completion scores
+#[derive(Debug, PartialEq)]
+struct Pair {
+    open: char,
+    close: char,
+    scores: Option<(u64, u64)>,
 }
 
-fn handle_close(stack: &mut Vec<char>, c: char) -> Result<(), char> {
-    //println!("  looking to {}, current stack {:?}", c, stack);
-    // top stack contains opening char
-    let last: char = *stack.last().unwrap();
-    // expected closing char
-    let expected: char = *pairs().get(&last).expect("No such pair");
-    if expected == c {
-        stack.pop();
-        Ok(())
-    } else {
-        Err(expected)
+/// Pair like `()` or `()=3:1` with syntax error and completion scores
+fn parse_pair(spec: &str) -> Pair {
+    let (brackets, scores) = match spec.split_once('=') {
+        Some((brackets, scores)) => (brackets, Some(scores)),
+        None => (spec, None),
+    };
+    let chars: Vec<char> = brackets.chars().collect();
+    let [open, close] = chars[..] else {
+        panic!("Pair must be an open and a close character: {}", spec);
+    };
+    let scores = scores.map(|scores| {
+        let (syntax, completion) = scores
+            .split_once(':')
+            .unwrap_or_else(|| panic!("Scores must be syntax:completion in {}", spec));
+        (
+            syntax.parse().expect("Wrong syntax score"),
+            completion.parse().expect("Wrong completion score"),
+        )
+    });
+    Pair {
+        open,
+        close,
+        scores,
     }
 }
 
-enum BraceResult {
-    Ok,
-    Corrupted(char),
-    Incomplete(Vec<char>),
+/// Bracket checker for a configurable set of pairs, other characters are ignored
+struct Checker {
+    pairs: Vec<Pair>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker::new(DEFAULT_PAIRS)
+    }
 }
 
-fn process_line(line: &str) -> BraceResult {
-    // Return () if okay or u32 with score if damaged
-    let mut stack: Vec<char> = Vec::new();
-    //println!("Processing line {}", line);
-    for c in line.chars() {
-        //println!(" processing symbol {}", c);
-        let res = match c {
-            '(' | '[' | '{' | '<' => handle_open(&mut stack, c),
-            ')' | ']' | '}' | '>' => handle_close(&mut stack, c),
-            _ => panic!("unknown symbol {}", c),
-        };
-        if let Err(expected) = res {
-            println!("Expected {}, but found {} instead", expected, c);
-            return BraceResult::Corrupted(c);
+impl Checker {
+    /// Checker from comma-separated pairs, like `(),[]=57:2`
+    fn new(pairs: &str) -> Checker {
+        let pairs: Vec<Pair> = pairs
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(parse_pair)
+            .collect();
+        assert!(!pairs.is_empty(), "No bracket pairs");
+        Checker { pairs }
+    }
+
+    /// Checker from `--pairs=...`, the puzzle brackets and scores by default
+    fn from_options() -> Checker {
+        common::option("pairs").map_or_else(Checker::default, Checker::new)
+    }
+
+    fn closer(&self, open: char) -> char {
+        self.pairs.iter().find(|p| p.open == open).unwrap().close
+    }
+
+    fn scores(&self, close: char) -> Option<(u64, u64)> {
+        self.pairs.iter().find(|p| p.close == close).unwrap().scores
+    }
+
+    /// Check text that may span several lines and report every error.
+    /// A closer matching a bracket deeper in the stack closes it together
+    /// with everything opened inside it, any other stray closer is skipped.
+    fn check(&self, text: &str) -> CheckResult {
+        let mut result = CheckResult::default();
+        let stack = &mut result.unclosed;
+        for (line_ind, line) in text.lines().enumerate() {
+            for (col_ind, c) in line.chars().enumerate() {
+                let pos = (line_ind + 1, col_ind + 1);
+                if self.pairs.iter().any(|p| p.open == c) {
+                    stack.push((pos, c));
+                    continue;
+                }
+                if !self.pairs.iter().any(|p| p.close == c) {
+                    continue;
+                }
+                let expected = stack.last().map(|(_, open)| self.closer(*open));
+                if expected == Some(c) {
+                    stack.pop();
+                    continue;
+                }
+                result.errors.push(SyntaxError {
+                    pos,
+                    expected,
+                    found: c,
+                });
+                if let Some(ind) = stack.iter().rposition(|(_, open)| self.closer(*open) == c) {
+                    stack.truncate(ind);
+                }
+            }
         }
+        result
+    }
+
+    /// Text closing all brackets left open
+    fn completion(&self, result: &CheckResult) -> String {
+        result
+            .unclosed
+            .iter()
+            .rev()
+            .map(|(_, open)| self.closer(*open))
+            .collect()
+    }
+
+    fn score_a(&self, c: char) -> Option<u64> {
+        self.scores(c).map(|(syntax, _)| syntax)
+    }
+
+    /// Completion score, none when some closer has no score
+    fn score_b_for_remaining(&self, remaining: &str) -> Option<u64> {
+        remaining.chars().try_fold(0, |accum, c| {
+            self.scores(c).map(|(_, completion)| accum * 5 + completion)
+        })
     }
-    if stack.is_empty() {
-        BraceResult::Ok
-    } else {
-        let mut remaining: Vec<char> = Vec::new();
-        while !stack.is_empty() {
-            let c = stack.pop().unwrap();
-            remaining.push(*pairs().get(&c).unwrap());
+}
+
+/// Print diagnostics for files, returns the number of problems found
+fn lint_files(checker: &Checker, filenames: &str) -> anyhow::Result<usize> {
+    let mut problems = 0;
+    for filename in filenames.split(',').filter(|s| !s.is_empty()) {
+        let result = checker.check(&std::fs::read_to_string(filename)?);
+        for error in &result.errors {
+            println!("{}:{}", filename, error);
         }
-        BraceResult::Incomplete(remaining)
+        for ((line, col), open) in &result.unclosed {
+            println!("{}:{}:{}: unclosed {}", filename, line, col, open);
+        }
+        if !result.unclosed.is_empty() {
+            println!(
+                "{}: complete with {}",
+                filename,
+                checker.completion(&result)
+            );
+        }
+        problems += result.errors.len() + result.unclosed.len();
     }
+    Ok(problems)
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
+fn maybe_lint_files(checker: &Checker) {
+    if let Some(filenames) = common::option("lint") {
+        let problems = lint_files(checker, filenames).expect("Cannot lint files");
+        println!("Found {} problems", problems);
+    }
+}
+
+/// Sum of scores of the first error on each corrupted line
+fn syntax_score(checker: &Checker, lines: &[String]) -> u64 {
     lines
         .iter()
-        .map(|line| match process_line(line) {
-            BraceResult::Ok => 0,
-            BraceResult::Corrupted(c) => {
-                let score = score_a(c);
-                println!("Registered score {} for line {}\n\n", score, line);
-                score as u64
+        .map(|line| match checker.check(line).errors.first() {
+            None => 0,
+            Some(error) => {
+                let score = checker.score_a(error.found).unwrap_or(0);
+                println!("{}, score {} for line {}", error, score, line);
+                score
             }
-            BraceResult::Incomplete(_remaining) => 0,
         })
         .sum()
 }
 
-fn score_b_for_remaining(remaining: &[char]) -> u64 {
-    remaining
-        .iter()
-        .map(|c| score_b(*c) as u64)
-        .reduce(|accum, item| accum * 5 + item)
-        .unwrap()
-}
-
-pub fn process_b(lines: Vec<String>) -> u64 {
+/// Middle score of completions of incomplete lines, lines with unscored closers are skipped
+fn completion_score(checker: &Checker, lines: &[String]) -> u64 {
     let mut scores: Vec<u64> = lines
         .iter()
-        .map(|line| match process_line(line) {
-            BraceResult::Ok => 0,
-            BraceResult::Corrupted(_c) => 0,
-            BraceResult::Incomplete(remaining) => {
-                println!("For line {}, remaining {:?}", line, remaining);
-                score_b_for_remaining(&remaining)
-            }
+        .map(|line| checker.check(line))
+        // drop corrupted and complete lines
+        .filter(|result| result.errors.is_empty() && !result.unclosed.is_empty())
+        .filter_map(|result| {
+            let remaining = checker.completion(&result);
+            println!("Complete with {}", remaining);
+            checker.score_b_for_remaining(&remaining)
         })
-        // drop corrupted and normal scores
-        .filter(|score| *score > 0)
         .collect();
 
     scores.sort();
@@ -131,3 +235,89 @@ pub fn process_b(lines: Vec<String>) -> u64 {
     let ind: usize = scores.len() / 2;
     scores[ind]
 }
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    let checker = Checker::from_options();
+    maybe_lint_files(&checker);
+    syntax_score(&checker, &lines)
+}
+
+pub fn process_b(lines: Vec<String>) -> u64 {
+    let checker = Checker::from_options();
+    maybe_lint_files(&checker);
+    completion_score(&checker, &lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_line() {
+        let checker = Checker::default();
+        let result = checker.check("{([(<{}[<>[]}>{[]{[(<()>");
+        assert_eq!(
+            result.errors[0],
+            SyntaxError {
+                pos: (1, 13),
+                expected: Some(']'),
+                found: '}'
+            }
+        );
+        let result = checker.check("[({(<(())[]>[[{[]{<()<>>");
+        assert!(result.errors.is_empty());
+        assert_eq!(checker.completion(&result), "}}]])})]");
+        assert_eq!(checker.score_b_for_remaining("}}]])})]"), Some(288957));
+    }
+
+    #[test]
+    fn test_all_errors() {
+        let checker = Checker::default();
+        let result = checker.check("(]\n[x)>\n{");
+        let errors: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:2: expected ), but found ] instead",
+                "2:3: expected ], but found ) instead",
+                "2:4: unexpected >, nothing is open",
+            ]
+        );
+        assert_eq!(result.unclosed, vec![((3, 1), '{')]);
+        assert_eq!(checker.completion(&result), "}");
+    }
+
+    #[test]
+    fn test_custom_pairs() {
+        let checker = Checker::new("(),«»");
+        let result = checker.check("fn(a, «b») {");
+        assert_eq!(result, CheckResult::default());
+        let result = checker.check("«(»");
+        assert_eq!(result.errors[0].expected, Some(')'));
+        assert_eq!(result.unclosed, vec![]);
+    }
+
+    #[test]
+    fn test_scored_custom_pairs() {
+        let lines: Vec<String> = [
+            "[({(<(())[]>[[{[]{<()<>>",
+            "[(()[<>])]({[<{<<[]>>(",
+            "{([(<{}[<>[]}>{[]{[(<()>",
+            "«(]",
+            "«[«",
+            "(«",
+            "<«(»",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let checker = Checker::new(&format!("{},«»=7:5", DEFAULT_PAIRS));
+        assert_eq!(syntax_score(&checker, &lines), 1197 + 57 + 7);
+        // completions score 288957, 5566, 5 * 25 + 2 * 5 + 5 = 140 and 5 * 5 + 1 = 26
+        assert_eq!(completion_score(&checker, &lines), 5566);
+
+        let unscored = Checker::new(&format!("{},«»", DEFAULT_PAIRS));
+        assert_eq!(syntax_score(&unscored, &lines), 1197 + 57);
+        assert_eq!(completion_score(&unscored, &lines), 288957);
+    }
+}
@@ -1,10 +1,13 @@
+use anyhow::anyhow;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::collections::VecDeque;
 use std::fmt;
 use std::hash::Hash;
 
-use crate::{answer, common::Answer};
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(79, 3621);
 
@@ -54,62 +57,109 @@ fn read_pos(line: &str) -> Pos {
     }
 }
 
+#[cfg(test)]
 enum Axis {
     X,
     Y,
     Z,
 }
 
-#[derive(Debug)]
+type Matrix = [[i32; 3]; 3];
+
+/// Proper rotation as an integer 3x3 matrix, one of 24 elements of the rotation group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct Transformation {
-    ix: i32,
-    iy: i32,
-    iz: i32,
+    matrix: Matrix,
 }
 
-fn rotate(x: i32, y: i32, angle: i32) -> (i32, i32) {
-    let rad = (angle as f32).to_radians();
-    let nx = ((x as f32) * rad.cos() - (y as f32) * rad.sin()).round() as i32;
-    let ny = ((y as f32) * rad.cos() + (x as f32) * rad.sin()).round() as i32;
-    (nx, ny)
-}
+impl Transformation {
+    const IDENTITY: Transformation = Transformation {
+        matrix: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+    };
+
+    /// Rotation around `axis` by `angle` degrees, must be a multiple of 90
+    #[cfg(test)]
+    fn rotation(axis: &Axis, angle: i32) -> Transformation {
+        let (cos, sin) = match angle.rem_euclid(360) {
+            0 => (1, 0),
+            90 => (0, 1),
+            180 => (-1, 0),
+            270 => (0, -1),
+            _ => panic!("Not a right angle {}", angle),
+        };
+        let matrix = match axis {
+            Axis::X => [[1, 0, 0], [0, cos, -sin], [0, sin, cos]],
+            Axis::Y => [[cos, 0, sin], [0, 1, 0], [-sin, 0, cos]],
+            Axis::Z => [[cos, -sin, 0], [sin, cos, 0], [0, 0, 1]],
+        };
+        Transformation { matrix }
+    }
 
-fn transform(pos: &Pos, axis: &Axis, angle: i32) -> Pos {
-    match axis {
-        Axis::X => {
-            let x = pos.x;
-            let (y, z) = rotate(pos.y, pos.z, angle);
-            Pos { x, y, z }
-        }
-        Axis::Y => {
-            let (x, z) = rotate(pos.x, pos.z, -angle);
-            let y = pos.y;
-            Pos { x, y, z }
+    /// Rotations around X, then Y, then Z axes
+    #[cfg(test)]
+    fn from_angles(ix: i32, iy: i32, iz: i32) -> Transformation {
+        Transformation::rotation(&Axis::Z, iz)
+            .compose(&Transformation::rotation(&Axis::Y, iy))
+            .compose(&Transformation::rotation(&Axis::X, ix))
+    }
+
+    /// Transformation applying `other` first and then `self`
+    fn compose(&self, other: &Transformation) -> Transformation {
+        let mut matrix: Matrix = [[0; 3]; 3];
+        for (row, matrix_row) in matrix.iter_mut().enumerate() {
+            for (col, cell) in matrix_row.iter_mut().enumerate() {
+                *cell = (0..3)
+                    .map(|k| self.matrix[row][k] * other.matrix[k][col])
+                    .sum();
+            }
         }
-        Axis::Z => {
-            let (x, y) = rotate(pos.x, pos.y, angle);
-            let z = pos.z;
-            Pos { x, y, z }
+        Transformation { matrix }
+    }
+
+    /// Inverse of a rotation is its transpose
+    fn inverse(&self) -> Transformation {
+        let mut matrix: Matrix = [[0; 3]; 3];
+        for (row, matrix_row) in matrix.iter_mut().enumerate() {
+            for (col, cell) in matrix_row.iter_mut().enumerate() {
+                *cell = self.matrix[col][row];
+            }
         }
+        Transformation { matrix }
     }
+
+    fn determinant(&self) -> i32 {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+#[cfg(test)]
+fn transform(pos: &Pos, axis: &Axis, angle: i32) -> Pos {
+    transform3(pos, &Transformation::rotation(axis, angle))
 }
 
 fn transform3(pos: &Pos, transformation: &Transformation) -> Pos {
-    let mut result = transform(pos, &Axis::X, transformation.ix);
-    result = transform(&result, &Axis::Y, transformation.iy);
-    result = transform(&result, &Axis::Z, transformation.iz);
-    result
+    let m = &transformation.matrix;
+    Pos {
+        x: m[0][0] * pos.x + m[0][1] * pos.y + m[0][2] * pos.z,
+        y: m[1][0] * pos.x + m[1][1] * pos.y + m[1][2] * pos.z,
+        z: m[2][0] * pos.x + m[2][1] * pos.y + m[2][2] * pos.z,
+    }
 }
 
+/// All 24 proper rotations: signed permutation matrices with determinant 1
 fn enumerate_transformations() -> Vec<Transformation> {
     let mut result = Vec::new();
-    for ix in [0, 90] {
-        for iy in [0, 90, 180, 270] {
-            for iz in [0, 90, 180, 270] {
-                if ix == 90 && (iy == 90 || iy == 270) {
-                    continue;
-                }
-                let transformation = Transformation { ix, iy, iz };
+    for permutation in (0..3).permutations(3) {
+        for signs in 0..8 {
+            let mut matrix: Matrix = [[0; 3]; 3];
+            for (row, col) in permutation.iter().enumerate() {
+                matrix[row][*col] = if signs & (1 << row) != 0 { -1 } else { 1 };
+            }
+            let transformation = Transformation { matrix };
+            if transformation.determinant() == 1 {
                 result.push(transformation);
             }
         }
@@ -131,6 +181,7 @@ fn enumerate_pos_transforms(pos: Pos) -> Vec<Pos> {
     result
 }
 
+#[cfg(test)]
 fn apply_transform(base: &View, transformation: &Transformation) -> View {
     View {
         beacons: base
@@ -159,6 +210,14 @@ fn shift(pos1: &Pos, pos2: &Pos) -> Pos {
     }
 }
 
+fn translate(pos: &Pos, by: &Pos) -> Pos {
+    Pos {
+        x: pos.x + by.x,
+        y: pos.y + by.y,
+        z: pos.z + by.z,
+    }
+}
+
 fn read_scanners(lines: Vec<String>) -> Vec<View> {
     let mut scanners: Vec<View> = Vec::new();
     for line in lines {
@@ -204,243 +263,347 @@ fn make_fingerprints(view: &View, capacity: usize) -> Fingerprints {
     fingerprints
 }
 
-fn check_align(pair1: &(Pos, Pos), pair2: &(Pos, Pos)) -> Option<Pos> {
-    let verbose = false;
-    let fw_shift = shift(&pair2.0, &pair1.0);
-    let bk_shift = shift(&pair2.1, &pair1.0);
-    let fw_shift2 = shift(&pair2.1, &pair1.1);
-    let bk_shift2 = shift(&pair2.0, &pair1.1);
-    if verbose {
-        println!("pair1 {:?}", pair1);
-        println!("pair2 {:?}", pair2);
-        println!(
-            "shift {:?} ; {:?} ; {:?} ; {:?}",
-            fw_shift, fw_shift2, bk_shift, bk_shift2
-        );
-    }
-
-    if fw_shift == fw_shift2 {
-        return Some(fw_shift);
-    }
-    if bk_shift == bk_shift2 {
-        return Some(bk_shift);
-    }
-    None
+#[allow(dead_code)]
+fn locate_scanner(base: &View, target: &View) -> Option<(Pos, Transformation)> {
+    locate_scanner_ex(base, target, 0, &AlignConfig::default())
 }
 
-/*
-fn detect_shift_majority(fingerprints1: &Fingerprints, fingerprints2: &Fingerprints) -> Option<Pos> {
-    let verbose = true;
-
-    let fdist1: HashSet<&u32> = fingerprints1.keys().collect();
-    let fdist2: HashSet<&u32> = fingerprints2.keys().collect();
-
-    let mut intersections: Vec<&u32> = fdist1.intersection(&fdist2).cloned().collect();
-    intersections.sort();
+/// Parameters of matching two scanner views
+#[derive(Debug, Clone)]
+struct AlignConfig {
+    // minimal number of common beacons to accept an alignment
+    overlap: usize,
+    // maximal deviation of each coordinate of a matched beacon
+    tolerance: i32,
+    // number of threads matching scanner pairs
+    threads: usize,
+}
 
-    //let common_count = intersections.len();
-    let common_count = intersections
-        .iter()
-        .map(|dist| fingerprints1[dist].len())
-        .sum::<usize>();
-    let required_edges = (COMMON_BEACONS) * ((COMMON_BEACONS) - 1) / 2;
-    if verbose {
-        println!(
-            "Common fingerpints: {}, required {}, total {}",
-            common_count,
-            required_edges,
-            fingerprints1.len()
-        );
-    }
-    if common_count < required_edges as usize {
-        return None;
+impl Default for AlignConfig {
+    fn default() -> Self {
+        AlignConfig {
+            overlap: COMMON_BEACONS,
+            tolerance: 0,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
     }
+}
 
-    let mut shifts_freq: HashMap<Pos, usize> = HashMap::new();
-
-    for dist in intersections {
-        let pair1 = fingerprints1[dist].first().unwrap();
-        let pair2 = fingerprints2[dist].first().unwrap();
-        if verbose {
-            println!("MAJ pair1 {:?}", pair1);
-            println!("MAJ pair2 {:?}", pair2);
-            println!(
-                "Look to dist {}, {} {}",
-                dist,
-                fingerprints1[dist].len(),
-                fingerprints2[dist].len(),
-            );
+impl AlignConfig {
+    /// Defaults overridden by `--overlap=N`, `--tolerance=N` and `--threads=N` options
+    fn from_options() -> AlignConfig {
+        let mut config = AlignConfig::default();
+        if let Some(overlap) = common::option("overlap") {
+            config.overlap = overlap.parse().expect("Wrong overlap");
         }
-        if let Some(shift) = check_align(pair1, pair2) {
-            //println!(" reg dist {} shift {:?}", dist, shift);
-            *shifts_freq.entry(shift).or_default() += 1;
+        if let Some(tolerance) = common::option("tolerance") {
+            config.tolerance = tolerance.parse().expect("Wrong tolerance");
         }
-    }
-    if !shifts_freq.is_empty() {
-        let top_freq: usize = *shifts_freq.values().max().unwrap();
-        if top_freq >= COMMON_BEACONS {
-            let majority_shift = shifts_freq.iter().find(|(k, v)| **v == top_freq).unwrap().0;
-            println!(
-                "Majority shift {:?} with freq {}",
-                majority_shift, top_freq
-            );
-            if shifts_freq.len() > 1 {
-                println!("NOTE there are other shifts {:?}", shifts_freq);
-            }
-            return Some(*majority_shift);
+        if let Some(threads) = common::option("threads") {
+            config.threads = threads.parse().expect("Wrong threads");
+            assert!(config.threads > 0, "Need at least one thread");
         }
+        config
     }
-    None
 }
-*/
 
-fn detect_shift(fingerprints1: &Fingerprints, fingerprints2: &Fingerprints) -> Option<Pos> {
-    let verbose = false;
-
-    let fdist1: HashSet<&u32> = fingerprints1.keys().collect();
-    let fdist2: HashSet<&u32> = fingerprints2.keys().collect();
+fn is_close(pos1: &Pos, pos2: &Pos, tolerance: i32) -> bool {
+    (pos1.x - pos2.x).abs() <= tolerance
+        && (pos1.y - pos2.y).abs() <= tolerance
+        && (pos1.z - pos2.z).abs() <= tolerance
+}
 
-    let mut intersections: Vec<&u32> = fdist1.intersection(&fdist2).cloned().collect();
-    intersections.sort();
+/// Target beacons that land close to some base beacon with the given pose
+fn find_inliers(
+    base: &View,
+    target: &View,
+    shift: &Pos,
+    transformation: &Transformation,
+    tolerance: i32,
+) -> Vec<(Pos, Pos)> {
+    target
+        .beacons
+        .iter()
+        .filter_map(|pos| {
+            let moved = translate(&transform3(pos, transformation), shift);
+            base.beacons
+                .iter()
+                .find(|base_pos| is_close(base_pos, &moved, tolerance))
+                .map(|base_pos| (*base_pos, *pos))
+        })
+        .collect()
+}
 
-    //let common_count = intersections.len();
-    let common_count = intersections
+/// Average shift of base beacons from rotated target beacons
+fn refine_shift(inliers: &[(Pos, Pos)], transformation: &Transformation) -> Pos {
+    let count = inliers.len() as f32;
+    let sum = inliers
         .iter()
-        .map(|dist| fingerprints1[dist].len())
-        .sum::<usize>();
-    let required_edges = (COMMON_BEACONS) * ((COMMON_BEACONS) - 1) / 2;
-    if verbose {
-        println!(
-            "Common fingerpints: {}, required {}, total {}",
-            common_count,
-            required_edges,
-            fingerprints1.len()
-        );
-    }
-    if common_count < required_edges as usize {
-        return None;
+        .fold(Pos { x: 0, y: 0, z: 0 }, |acc, (base, pos)| {
+            translate(&acc, &shift(&transform3(pos, transformation), base))
+        });
+    Pos {
+        x: (sum.x as f32 / count).round() as i32,
+        y: (sum.y as f32 / count).round() as i32,
+        z: (sum.z as f32 / count).round() as i32,
     }
+}
 
-    // Assume there could be only one shift
-    // Previous version had selected a majority which is not needed for test data
-    let mut shift_count = 0;
-    let mut last_shift: Option<Pos> = None;
-
-    for dist in intersections {
-        let pair1 = fingerprints1[dist].first().unwrap();
-        let pair2 = fingerprints2[dist].first().unwrap();
-        if verbose {
-            println!("MAJ pair1 {:?}", pair1);
-            println!("MAJ pair2 {:?}", pair2);
-            println!(
-                "Look to dist {}, {} {}",
-                dist,
-                fingerprints1[dist].len(),
-                fingerprints2[dist].len(),
-            );
-        }
-        if let Some(shift) = check_align(pair1, pair2) {
-            shift_count += 1;
-            last_shift = Some(shift);
+/// Consensus alignment: each pair of beacons with a similar distance in both views
+/// proposes a pose, the pose with most matched beacons wins
+fn align_scanners(
+    base: &View,
+    fingerprints_base: &Fingerprints,
+    target: &View,
+    fingerprints_target: &Fingerprints,
+    config: &AlignConfig,
+) -> Option<(Pos, Transformation, usize)> {
+    let transformations = enumerate_transformations();
+    // distance between two noisy beacons deviates at most by this value
+    let max_deviation = (2.0 * 3f32.sqrt() * config.tolerance as f32).ceil() as u32;
+
+    let mut tried: HashSet<(Transformation, Pos)> = HashSet::new();
+    let mut best: Option<(Vec<(Pos, Pos)>, Transformation)> = None;
+
+    let mut distances: Vec<&u32> = fingerprints_base.keys().collect();
+    distances.sort();
+    for dist in distances {
+        let dist_range = dist.saturating_sub(max_deviation)..=dist + max_deviation;
+        for target_dist in dist_range {
+            let Some(pairs_target) = fingerprints_target.get(&target_dist) else {
+                continue;
+            };
+            for (base1, base2) in &fingerprints_base[dist] {
+                let base_vector = shift(base1, base2);
+                // target pair could be matched in both directions
+                for (pos1, pos2) in pairs_target
+                    .iter()
+                    .flat_map(|(pos1, pos2)| [(*pos1, *pos2), (*pos2, *pos1)])
+                {
+                    for transformation in &transformations {
+                        let target_vector = transform3(&shift(&pos1, &pos2), transformation);
+                        if !is_close(&target_vector, &base_vector, 2 * config.tolerance) {
+                            continue;
+                        }
+                        let hypothesis = shift(&transform3(&pos1, transformation), base1);
+                        if !tried.insert((*transformation, hypothesis)) {
+                            continue;
+                        }
+                        let inliers = find_inliers(
+                            base,
+                            target,
+                            &hypothesis,
+                            transformation,
+                            config.tolerance,
+                        );
+                        if best
+                            .as_ref()
+                            .is_none_or(|(best_inliers, _)| inliers.len() > best_inliers.len())
+                        {
+                            best = Some((inliers, *transformation));
+                        }
+                    }
+                }
+            }
         }
     }
-    if shift_count >= COMMON_BEACONS {
-        return last_shift;
-    }
-    None
-}
 
-#[allow(dead_code)]
-fn locate_scanner(base: &View, target: &View) -> Option<(Pos, Transformation)> {
-    locate_scanner_ex(base, target, 0)
+    let (inliers, transformation) = best?;
+    if inliers.len() < config.overlap {
+        return None;
+    }
+    let ashift = refine_shift(&inliers, &transformation);
+    Some((ashift, transformation, inliers.len()))
 }
 
 fn locate_scanner_ex(
     base: &View,
     target: &View,
     estimated_fingerprints: usize,
+    config: &AlignConfig,
 ) -> Option<(Pos, Transformation)> {
     let fingerprints_base = make_fingerprints(base, estimated_fingerprints);
+    let fingerprints_target = make_fingerprints(target, estimated_fingerprints);
+    let (ashift, transformation, overlap) = align_scanners(
+        base,
+        &fingerprints_base,
+        target,
+        &fingerprints_target,
+        config,
+    )?;
+    println!("Possible shift {:?} with {} beacons", ashift, overlap);
+    println!("Transformation was {:?}", &transformation);
+    Some((ashift, transformation))
+}
 
-    for transformation in enumerate_transformations() {
-        let rotation = apply_transform(target, &transformation);
-
-        let fingerprints_target = make_fingerprints(&rotation, estimated_fingerprints);
-        let shift = detect_shift(&fingerprints_base, &fingerprints_target);
-        if let Some(ashift) = shift {
-            println!("Possible shift {:?}", ashift);
-            println!("Transformation was {:?}", &transformation);
-            return Some((ashift, transformation));
+type Pose = (Pos, Transformation);
+
+type ResolvedScanners = HashMap<u32, Pose>;
+
+/// Pose `rel` given relative to pose `base` expressed in the frame of `base`
+fn compose_pose(base: &Pose, rel: &Pose) -> Pose {
+    (
+        translate(&transform3(&rel.0, &base.1), &base.0),
+        base.1.compose(&rel.1),
+    )
+}
+
+fn inverse_pose(pose: &Pose) -> Pose {
+    let inverse = pose.1.inverse();
+    let loc = transform3(&pose.0, &inverse);
+    (
+        Pos {
+            x: -loc.x,
+            y: -loc.y,
+            z: -loc.z,
+        },
+        inverse,
+    )
+}
+
+/// Alignment of scanner `j` relative to scanner `i` for `(i, j)` with `i < j`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edge {
+    pose: Pose,
+    overlap: usize,
+}
+
+type OverlapGraph = BTreeMap<(u32, u32), Edge>;
+
+/// Align all pairs of scanners using `config.threads` threads, fingerprints are made once
+/// per scanner and the result does not depend on the number of threads
+fn build_overlap_graph(scanners: &[View], config: &AlignConfig) -> OverlapGraph {
+    let count = scanners.len() as u32;
+    let estimated_fg_count = make_fingerprints(&scanners[0], 0).len();
+    let fingerprints: Vec<Fingerprints> = scanners
+        .iter()
+        .map(|view| make_fingerprints(view, estimated_fg_count))
+        .collect();
+    let pairs: Vec<(u32, u32)> = (0..count)
+        .flat_map(|source| (source + 1..count).map(move |target| (source, target)))
+        .collect();
+
+    let mut graph: OverlapGraph = BTreeMap::new();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|worker| {
+                let pairs = &pairs;
+                let fingerprints = &fingerprints;
+                scope.spawn(move || {
+                    pairs
+                        .iter()
+                        .skip(worker)
+                        .step_by(config.threads)
+                        .filter_map(|(source, target)| {
+                            let (s, t) = (*source as usize, *target as usize);
+                            let (loc, transformation, overlap) = align_scanners(
+                                &scanners[s],
+                                &fingerprints[s],
+                                &scanners[t],
+                                &fingerprints[t],
+                                config,
+                            )?;
+                            let pose = (loc, transformation);
+                            Some(((*source, *target), Edge { pose, overlap }))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for worker in workers {
+            graph.extend(worker.join().unwrap());
         }
+    });
+    for ((source, target), edge) in &graph {
+        println!(
+            "Aligned {} to {} with {} beacons: {:?}",
+            target, source, edge.overlap, edge.pose
+        );
     }
-    None
+    graph
 }
 
-type ResolvedScanners = HashMap<u32, (Pos, Transformation)>;
+/// Pose of scanner `to` in the frame of scanner `from`
+fn edge_pose(graph: &OverlapGraph, from: u32, to: u32) -> Option<Pose> {
+    if from < to {
+        graph.get(&(from, to)).map(|edge| edge.pose)
+    } else {
+        graph.get(&(to, from)).map(|edge| inverse_pose(&edge.pose))
+    }
+}
 
-fn detect_scanners(scanners: &Vec<View>) -> ResolvedScanners {
+/// Place scanners along the maximum spanning tree so the strongest alignments are used
+fn place_scanners(graph: &OverlapGraph, count: usize) -> ResolvedScanners {
     let mut resolved_scanners: ResolvedScanners = HashMap::new();
+    resolved_scanners.insert(0, (Pos { x: 0, y: 0, z: 0 }, Transformation::IDENTITY));
 
-    let count = scanners.len() as u32;
+    loop {
+        let best_edge = graph
+            .iter()
+            .filter(|((i, j), _)| {
+                resolved_scanners.contains_key(i) != resolved_scanners.contains_key(j)
+            })
+            .max_by_key(|((i, j), edge)| (edge.overlap, Reverse(*i), Reverse(*j)));
+        let Some(((i, j), _)) = best_edge else {
+            break;
+        };
+        let (source, target) = if resolved_scanners.contains_key(i) {
+            (*i, *j)
+        } else {
+            (*j, *i)
+        };
+        println!("Place {} from {}", target, source);
+        let rel_pose = edge_pose(graph, source, target).unwrap();
+        let pose = compose_pose(&resolved_scanners[&source], &rel_pose);
+        resolved_scanners.insert(target, pose);
+    }
+    assert!(resolved_scanners.len() <= count);
 
-    let mut queue: VecDeque<u32> = VecDeque::new();
-    let mut discovered: Vec<u32> = vec![];
+    resolved_scanners
+}
 
-    let estimated_fg_count = make_fingerprints(&scanners[0], 0).len();
+/// Edges whose relative pose disagrees with placed poses, so a cycle through
+/// the spanning tree does not compose to identity
+fn find_inconsistent_edges(
+    graph: &OverlapGraph,
+    resolved_scanners: &ResolvedScanners,
+    tolerance: i32,
+) -> Vec<(u32, u32)> {
+    graph
+        .iter()
+        .filter(|((i, j), edge)| {
+            let (Some(pose_i), Some(pose_j)) = (resolved_scanners.get(i), resolved_scanners.get(j))
+            else {
+                return false;
+            };
+            let cycle = compose_pose(&compose_pose(&inverse_pose(pose_j), pose_i), &edge.pose);
+            cycle.1 != Transformation::IDENTITY
+                || !is_close(&cycle.0, &Pos { x: 0, y: 0, z: 0 }, 2 * tolerance)
+        })
+        .map(|(key, _)| *key)
+        .collect()
+}
 
-    queue.push_back(0u32);
-    discovered.push(0u32);
-    resolved_scanners.insert(
-        0,
-        (
-            Pos { x: 0, y: 0, z: 0 },
-            Transformation {
-                ix: 0,
-                iy: 0,
-                iz: 0,
-            },
-        ),
+fn detect_scanners(scanners: &Vec<View>) -> ResolvedScanners {
+    detect_scanners_ex(scanners, &AlignConfig::from_options())
+}
+
+/// Scanners that could not be aligned with any placed scanner
+fn unplaced_scanners(resolved_scanners: &ResolvedScanners, count: usize) -> Vec<u32> {
+    (0..count as u32)
+        .filter(|idx| !resolved_scanners.contains_key(idx))
+        .collect()
+}
+
+fn detect_scanners_ex(scanners: &[View], config: &AlignConfig) -> ResolvedScanners {
+    let graph = build_overlap_graph(scanners, config);
+    println!(
+        "Overlap graph has {} edges: {:?}",
+        graph.len(),
+        graph.keys().collect::<Vec<_>>()
     );
 
-    while !queue.is_empty() {
-        let source = queue.pop_front().unwrap();
-        //println!(
-        //"Handle {}, queue {:?}, discovered {:?}",
-        //source, queue, discovered
-        //);
-
-        let adjacents = (0..count).filter(|x| *x != source);
-
-        for target in adjacents {
-            if !discovered.contains(&target) {
-                //println!("Check from {} to {}", source, target);
-                let transformed_source =
-                    apply_transform(&scanners[source as usize], &resolved_scanners[&source].1);
-                if let Some((rel_loc, transformation)) = locate_scanner_ex(
-                    &transformed_source,
-                    &scanners[target as usize],
-                    estimated_fg_count,
-                ) {
-                    println!("Enqueue {}", target);
-                    let base_loc = resolved_scanners[&source].0;
-                    let abs_loc = Pos {
-                        x: rel_loc.x + base_loc.x,
-                        y: rel_loc.y + base_loc.y,
-                        z: rel_loc.z + base_loc.z,
-                    };
-                    /*
-                    println!("=== Resolved target {}", target);
-                    println!("Base location of {} is {:?}", source, base_loc);
-                    println!("Rel location {:?}", rel_loc);
-                    println!("Abs location {:?}", abs_loc);
-                    println!("Transformation {:?}", transformation);*/
-                    resolved_scanners.insert(target as u32, (abs_loc, transformation));
-                    queue.push_back(target);
-                    discovered.push(target);
-                }
-            }
-        }
-    }
+    let resolved_scanners = place_scanners(&graph, scanners.len());
 
     println!(
         "Found locations {}: {:?}",
@@ -450,27 +613,145 @@ fn detect_scanners(scanners: &Vec<View>) -> ResolvedScanners {
     for (k, v) in &resolved_scanners {
         println!("Scanner {} at {:?}, transformation {:?}", k, v.0, v.1);
     }
+    let unplaced = unplaced_scanners(&resolved_scanners, scanners.len());
+    if !unplaced.is_empty() {
+        println!(
+            "Could not place {} scanners with overlap {}: {:?}",
+            unplaced.len(),
+            config.overlap,
+            unplaced
+        );
+    }
+    let inconsistent = find_inconsistent_edges(&graph, &resolved_scanners, config.tolerance);
+    if !inconsistent.is_empty() {
+        println!("Inconsistent alignments: {:?}", inconsistent);
+    }
 
     resolved_scanners
 }
 
-fn relocate_beacons(resolved_scanners: &ResolvedScanners, scanners: &[View]) -> Vec<Pos> {
-    let mut absolute_locations: HashSet<Pos> = HashSet::new();
+// Map from absolute beacon location to scanners that observed it
+type ObservedBeacons = BTreeMap<Pos, Vec<u32>>;
+
+fn observe_beacons(resolved_scanners: &ResolvedScanners, scanners: &[View]) -> ObservedBeacons {
+    let mut observed: ObservedBeacons = BTreeMap::new();
 
     for (idx, scanner) in scanners.iter().enumerate() {
+        // skip unplaced scanners
+        let Some((base_loc, transformation)) = resolved_scanners.get(&(idx as u32)) else {
+            continue;
+        };
         for rel_beacon in &scanner.beacons {
-            let (base_loc, transformation) = &resolved_scanners[&(idx as u32)];
-            let rel_loc = transform3(rel_beacon, transformation);
-            let abs_loc = Pos {
-                x: rel_loc.x + base_loc.x,
-                y: rel_loc.y + base_loc.y,
-                z: rel_loc.z + base_loc.z,
-            };
-            absolute_locations.insert(abs_loc);
+            let abs_loc = translate(&transform3(rel_beacon, transformation), base_loc);
+            let observers = observed.entry(abs_loc).or_default();
+            if !observers.contains(&(idx as u32)) {
+                observers.push(idx as u32);
+            }
         }
     }
 
-    absolute_locations.iter().copied().collect::<Vec<Pos>>()
+    observed
+}
+
+fn relocate_beacons(resolved_scanners: &ResolvedScanners, scanners: &[View]) -> Vec<Pos> {
+    observe_beacons(resolved_scanners, scanners)
+        .keys()
+        .copied()
+        .collect::<Vec<Pos>>()
+}
+
+fn sorted_scanner_ids(resolved_scanners: &ResolvedScanners) -> Vec<u32> {
+    let mut ids: Vec<u32> = resolved_scanners.keys().copied().collect();
+    ids.sort();
+    ids
+}
+
+fn format_map_json(resolved_scanners: &ResolvedScanners, observed: &ObservedBeacons) -> String {
+    let scanners_json: Vec<String> = sorted_scanner_ids(resolved_scanners)
+        .iter()
+        .map(|id| {
+            let (pos, transformation) = &resolved_scanners[id];
+            let rows: Vec<String> = transformation
+                .matrix
+                .iter()
+                .map(|row| format!("[{}, {}, {}]", row[0], row[1], row[2]))
+                .collect();
+            format!(
+                "    {{\"id\": {}, \"position\": [{}, {}, {}], \"rotation\": [{}]}}",
+                id,
+                pos.x,
+                pos.y,
+                pos.z,
+                rows.join(", ")
+            )
+        })
+        .collect();
+    let beacons_json: Vec<String> = observed
+        .iter()
+        .map(|(pos, observers)| {
+            format!(
+                "    {{\"position\": [{}, {}, {}], \"observed_by\": [{}]}}",
+                pos.x,
+                pos.y,
+                pos.z,
+                observers.iter().map(|id| id.to_string()).join(", ")
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"scanners\": [\n{}\n  ],\n  \"beacons\": [\n{}\n  ]\n}}\n",
+        scanners_json.join(",\n"),
+        beacons_json.join(",\n")
+    )
+}
+
+/// ASCII PLY with beacons in white and scanners in red
+fn format_map_ply(resolved_scanners: &ResolvedScanners, observed: &ObservedBeacons) -> String {
+    let mut result = String::new();
+    result += "ply\nformat ascii 1.0\n";
+    result += &format!(
+        "element vertex {}\n",
+        observed.len() + resolved_scanners.len()
+    );
+    result += "property int x\nproperty int y\nproperty int z\n";
+    result += "property uchar red\nproperty uchar green\nproperty uchar blue\n";
+    result += "end_header\n";
+    for pos in observed.keys() {
+        result += &format!("{} {} {} 255 255 255\n", pos.x, pos.y, pos.z);
+    }
+    for id in sorted_scanner_ids(resolved_scanners) {
+        let pos = resolved_scanners[&id].0;
+        result += &format!("{} {} {} 255 0 0\n", pos.x, pos.y, pos.z);
+    }
+    result
+}
+
+/// Plain XYZ point cloud of beacons
+fn format_map_xyz(observed: &ObservedBeacons) -> String {
+    observed
+        .keys()
+        .map(|pos| format!("{} {} {}\n", pos.x, pos.y, pos.z))
+        .collect()
+}
+
+/// Write assembled map to each of comma-separated files, format is chosen by extension
+fn export_map(
+    resolved_scanners: &ResolvedScanners,
+    scanners: &[View],
+    filenames: &str,
+) -> anyhow::Result<()> {
+    let observed = observe_beacons(resolved_scanners, scanners);
+    for filename in filenames.split(',').filter(|s| !s.is_empty()) {
+        let content = match filename.rsplit('.').next() {
+            Some("json") => format_map_json(resolved_scanners, &observed),
+            Some("ply") => format_map_ply(resolved_scanners, &observed),
+            Some("xyz") => format_map_xyz(&observed),
+            _ => return Err(anyhow!("Unknown export format of {}", filename)),
+        };
+        std::fs::write(filename, content)?;
+        println!("Exported map to {}", filename);
+    }
+    Ok(())
 }
 
 fn total_scanners_distance(resolved_scanners: &ResolvedScanners) -> u64 {
@@ -485,11 +766,18 @@ fn total_scanners_distance(resolved_scanners: &ResolvedScanners) -> u64 {
     *distances.iter().max().unwrap_or(&0) as u64
 }
 
+fn maybe_export_map(resolved_scanners: &ResolvedScanners, scanners: &[View]) {
+    if let Some(filenames) = common::option("export") {
+        export_map(resolved_scanners, scanners, filenames).expect("Cannot export map");
+    }
+}
+
 pub fn process_a(lines: Vec<String>) -> u64 {
     let scanners = read_scanners(lines);
     println!("Total scanners {:?}", scanners.len());
 
     let resolved = detect_scanners(&scanners);
+    maybe_export_map(&resolved, &scanners);
     let abs_beacons = relocate_beacons(&resolved, &scanners);
     abs_beacons.len() as u64
 }
@@ -499,12 +787,15 @@ pub fn process_b(lines: Vec<String>) -> u64 {
     println!("Total scanners {:?}", scanners.len());
 
     let resolved = detect_scanners(&scanners);
+    maybe_export_map(&resolved, &scanners);
     total_scanners_distance(&resolved)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::collections::HashSet;
 
     fn read_scanner(sample: &str) -> View {
@@ -551,28 +842,14 @@ mod tests {
     #[test]
     fn test_transform3() {
         let pos = read_pos("4,1,2");
-        let npos = transform3(
-            &pos,
-            &Transformation {
-                ix: 180,
-                iy: 0,
-                iz: 0,
-            },
-        );
+        let npos = transform3(&pos, &Transformation::from_angles(180, 0, 0));
         assert_eq!(npos, read_pos("4,-1,-2"));
     }
 
     #[test]
     fn test_transform3_dual() {
         let pos = read_pos("4,1,2");
-        let npos = transform3(
-            &pos,
-            &Transformation {
-                ix: 180,
-                iy: 90,
-                iz: 270,
-            },
-        );
+        let npos = transform3(&pos, &Transformation::from_angles(180, 90, 270));
         // x => 4,-1,-2
         // t => -2,-1,-4 (negative sin)
         // z => -2,1,-4
@@ -829,19 +1106,19 @@ mod tests {
         let s2: &View = &read_scanner(SAMPLE_C1);
 
         // Task description lacks explanation that C1 must be rotated before
-        let s2x = apply_transform(
-            s2,
-            &Transformation {
-                ix: 0,
-                iy: 180,
-                iz: 0,
-            },
-        );
+        let s2x = apply_transform(s2, &Transformation::from_angles(0, 180, 0));
 
         let fingerprints_s1 = make_fingerprints(&s1, 0);
         let fingerprints_s2 = make_fingerprints(&s2x, 0);
 
-        assert!(detect_shift(&fingerprints_s1, &fingerprints_s2).is_some());
+        let aligned = align_scanners(
+            s1,
+            &fingerprints_s1,
+            &s2x,
+            &fingerprints_s2,
+            &AlignConfig::default(),
+        );
+        assert_eq!(aligned.map(|a| a.1), Some(Transformation::IDENTITY));
     }
 
     #[test]
@@ -883,7 +1160,14 @@ mod tests {
         let s0: &View = &read_scanner(SAMPLE_B0);
         let s1: &View = &read_scanner(SAMPLE_B4);
 
-        let shift = locate_scanner(&s0, &s1);
+        // scanners 0 and 4 share only 6 beacons
+        assert!(locate_scanner(s0, s1).is_none());
+        let config = AlignConfig {
+            overlap: 6,
+            tolerance: 0,
+            ..AlignConfig::default()
+        };
+        let shift = locate_scanner_ex(s0, s1, 0, &config);
         assert!(shift.is_some());
         assert_eq!(
             shift.unwrap().0,
@@ -994,4 +1278,318 @@ mod tests {
         let total_dist = total_scanners_distance(&resolved);
         assert_eq!(total_dist, 3621);
     }
+
+    #[test]
+    fn test_rotation_matrix() {
+        assert_eq!(
+            Transformation::from_angles(0, 0, 90).matrix,
+            [[0, -1, 0], [1, 0, 0], [0, 0, 1]]
+        );
+        assert_eq!(
+            Transformation::from_angles(0, 0, 0),
+            Transformation::IDENTITY
+        );
+    }
+
+    #[test]
+    fn test_rotation_group() {
+        let transformations = enumerate_transformations();
+        for t1 in &transformations {
+            assert_eq!(t1.compose(&t1.inverse()), Transformation::IDENTITY);
+            for t2 in &transformations {
+                // group is closed under composition
+                assert!(transformations.contains(&t1.compose(t2)));
+                let pos = read_pos("4,1,2");
+                assert_eq!(
+                    transform3(&pos, &t1.compose(t2)),
+                    transform3(&transform3(&pos, t2), t1)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_export_map() {
+        let scanners = _all_views();
+        let resolved = detect_scanners(&scanners);
+        let observed = observe_beacons(&resolved, &scanners);
+        assert_eq!(observed.len(), 79);
+        // all beacons of scanner 0 are observed by it
+        assert_eq!(
+            observed.values().filter(|ids| ids.contains(&0)).count(),
+            scanners[0].beacons.len()
+        );
+        assert!(observed.values().any(|ids| ids.len() > 1));
+
+        let json = format_map_json(&resolved, &observed);
+        assert!(json.contains("{\"id\": 1, \"position\": [68, -1246, -43], \"rotation\": "));
+        assert_eq!(json.matches("observed_by").count(), 79);
+
+        let ply = format_map_ply(&resolved, &observed);
+        assert!(ply.contains("element vertex 84\n"));
+        assert_eq!(ply.lines().count(), 10 + 84);
+
+        assert_eq!(format_map_xyz(&observed).lines().count(), 79);
+    }
+
+    /// Shift every coordinate by -1, 0 or 1 in a deterministic pattern
+    fn add_noise(view: &View) -> View {
+        View {
+            beacons: view
+                .beacons
+                .iter()
+                .enumerate()
+                .map(|(idx, pos)| {
+                    let idx = idx as i32;
+                    Pos {
+                        x: pos.x + idx % 3 - 1,
+                        y: pos.y + (idx / 3) % 3 - 1,
+                        z: pos.z + (idx / 9) % 3 - 1,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_locate_noisy() {
+        let s0: &View = &read_scanner(SAMPLE_B0);
+        let mut s1: View = add_noise(&read_scanner(SAMPLE_B1));
+        s1.beacons.push(read_pos("1,2,3"));
+
+        assert!(locate_scanner(s0, &s1).is_none());
+        let config = AlignConfig {
+            overlap: 12,
+            tolerance: 2,
+            ..AlignConfig::default()
+        };
+        let (loc, _) = locate_scanner_ex(s0, &s1, 0, &config).unwrap();
+        assert!(is_close(&loc, &read_pos("68,-1246,-43"), 1));
+    }
+
+    #[test]
+    fn test_unplaced_scanners() {
+        let mut scanners = _all_views();
+        // scanner 3 sees only 3 beacons now
+        scanners[3].beacons.truncate(3);
+        let resolved = detect_scanners_ex(&scanners, &AlignConfig::default());
+        assert_eq!(unplaced_scanners(&resolved, scanners.len()), vec![3]);
+        assert!(relocate_beacons(&resolved, &scanners).len() < 79);
+
+        let resolved = detect_scanners_ex(
+            &_all_views(),
+            &AlignConfig {
+                overlap: 13,
+                tolerance: 0,
+                ..AlignConfig::default()
+            },
+        );
+        assert!(!unplaced_scanners(&resolved, 5).is_empty());
+    }
+
+    #[test]
+    fn test_pose_inverse() {
+        let pose = (
+            read_pos("68,-1246,-43"),
+            Transformation::from_angles(0, 90, 270),
+        );
+        let identity = compose_pose(&pose, &inverse_pose(&pose));
+        assert_eq!(identity, (read_pos("0,0,0"), Transformation::IDENTITY));
+        let identity = compose_pose(&inverse_pose(&pose), &pose);
+        assert_eq!(identity, (read_pos("0,0,0"), Transformation::IDENTITY));
+    }
+
+    #[test]
+    fn test_overlap_graph_consistency() {
+        let scanners = _all_views();
+        let config = AlignConfig {
+            overlap: 6,
+            tolerance: 0,
+            ..AlignConfig::default()
+        };
+        let mut graph = build_overlap_graph(&scanners, &config);
+        for edge in [(0, 1), (0, 4), (1, 3), (1, 4), (2, 4)] {
+            assert!(graph.contains_key(&edge));
+        }
+        let resolved = place_scanners(&graph, scanners.len());
+        assert_eq!(resolved[&2].0, read_pos("1105,-1205,1229"));
+        assert!(find_inconsistent_edges(&graph, &resolved, 0).is_empty());
+
+        // break the cycle 0-1-4
+        graph.get_mut(&(0, 4)).unwrap().pose.0.x += 5;
+        let inconsistent = find_inconsistent_edges(&graph, &resolved, 0);
+        assert_eq!(inconsistent.len(), 1);
+        assert!(find_inconsistent_edges(&graph, &resolved, 3).is_empty());
+    }
+
+    #[test]
+    fn test_overlap_graph_threads() {
+        let scanners = _all_views();
+        let single = AlignConfig {
+            overlap: 6,
+            threads: 1,
+            ..AlignConfig::default()
+        };
+        let graph = build_overlap_graph(&scanners, &single);
+        for threads in [2, 3, 16] {
+            let config = AlignConfig {
+                threads,
+                ..single.clone()
+            };
+            assert_eq!(build_overlap_graph(&scanners, &config), graph);
+        }
+    }
+
+    /// Parameters of a synthetic scanner report
+    struct Synthetic {
+        seed: u64,
+        scanners: usize,
+        // beacons placed where a scanner overlaps with one placed before
+        shared_beacons: usize,
+        // beacons placed anywhere in range of a scanner
+        own_beacons: usize,
+        // maximal deviation of each reading coordinate
+        noise: i32,
+        // probability to miss a visible beacon
+        missing: f64,
+    }
+
+    const RANGE: i32 = 1000;
+
+    /// Generate report lines and ground truth poses of scanners. Each scanner is placed
+    /// in range of a random earlier one with common beacons between them.
+    fn generate_report(params: &Synthetic) -> (Vec<String>, Vec<Pose>) {
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let transformations = enumerate_transformations();
+        let random_in = |rng: &mut StdRng, low: &Pos, high: &Pos| Pos {
+            x: rng.gen_range(low.x..=high.x),
+            y: rng.gen_range(low.y..=high.y),
+            z: rng.gen_range(low.z..=high.z),
+        };
+
+        let mut poses: Vec<Pose> = vec![(read_pos("0,0,0"), Transformation::IDENTITY)];
+        let mut beacons: Vec<Pos> = vec![];
+        for idx in 0..params.scanners {
+            if idx > 0 {
+                let parent = poses[rng.gen_range(0..idx)].0;
+                let reach = RANGE * 3 / 2;
+                let offset = random_in(
+                    &mut rng,
+                    &Pos {
+                        x: -reach,
+                        y: -reach,
+                        z: -reach,
+                    },
+                    &Pos {
+                        x: reach,
+                        y: reach,
+                        z: reach,
+                    },
+                );
+                let loc = translate(&parent, &offset);
+                let transformation = transformations[rng.gen_range(0..transformations.len())];
+                // box seen by both parent and new scanner
+                let low = Pos {
+                    x: parent.x.max(loc.x) - RANGE,
+                    y: parent.y.max(loc.y) - RANGE,
+                    z: parent.z.max(loc.z) - RANGE,
+                };
+                let high = Pos {
+                    x: parent.x.min(loc.x) + RANGE,
+                    y: parent.y.min(loc.y) + RANGE,
+                    z: parent.z.min(loc.z) + RANGE,
+                };
+                for _ in 0..params.shared_beacons {
+                    beacons.push(random_in(&mut rng, &low, &high));
+                }
+                poses.push((loc, transformation));
+            }
+            let loc = poses[idx].0;
+            let low = translate(&loc, &read_pos("-1000,-1000,-1000"));
+            let high = translate(&loc, &read_pos("1000,1000,1000"));
+            for _ in 0..params.own_beacons {
+                beacons.push(random_in(&mut rng, &low, &high));
+            }
+        }
+        beacons.sort();
+        beacons.dedup();
+
+        let mut lines: Vec<String> = vec![];
+        for (idx, pose) in poses.iter().enumerate() {
+            lines.push(format!("--- scanner {} ---", idx));
+            let local_pose = inverse_pose(pose);
+            for beacon in &beacons {
+                if !is_close(beacon, &pose.0, RANGE) || rng.gen_bool(params.missing) {
+                    continue;
+                }
+                let reading = compose_pose(&local_pose, &(*beacon, Transformation::IDENTITY)).0;
+                let noise = random_in(
+                    &mut rng,
+                    &Pos {
+                        x: -params.noise,
+                        y: -params.noise,
+                        z: -params.noise,
+                    },
+                    &Pos {
+                        x: params.noise,
+                        y: params.noise,
+                        z: params.noise,
+                    },
+                );
+                let reading = translate(&reading, &noise);
+                lines.push(format!("{},{},{}", reading.x, reading.y, reading.z));
+            }
+            lines.push(String::new());
+        }
+        (lines, poses)
+    }
+
+    #[test]
+    fn test_synthetic_exact() {
+        let params = Synthetic {
+            seed: 19,
+            scanners: 8,
+            shared_beacons: 14,
+            own_beacons: 10,
+            noise: 0,
+            missing: 0.0,
+        };
+        let (lines, poses) = generate_report(&params);
+        let scanners = read_scanners(lines);
+        assert_eq!(scanners.len(), 8);
+
+        let resolved = detect_scanners_ex(&scanners, &AlignConfig::default());
+        for (idx, pose) in poses.iter().enumerate() {
+            assert_eq!(resolved[&(idx as u32)], *pose);
+        }
+        let abs_beacons = relocate_beacons(&resolved, &scanners);
+        let seen: usize = scanners.iter().map(|view| view.beacons.len()).sum();
+        assert!(abs_beacons.len() < seen);
+        assert!(abs_beacons.len() >= 8 * 10);
+    }
+
+    #[test]
+    fn test_synthetic_noisy() {
+        let params = Synthetic {
+            seed: 2021,
+            scanners: 6,
+            shared_beacons: 20,
+            own_beacons: 5,
+            noise: 1,
+            missing: 0.1,
+        };
+        let (lines, poses) = generate_report(&params);
+        let scanners = read_scanners(lines);
+        let config = AlignConfig {
+            tolerance: 2,
+            ..AlignConfig::default()
+        };
+        let resolved = detect_scanners_ex(&scanners, &config);
+        assert!(unplaced_scanners(&resolved, scanners.len()).is_empty());
+        for (idx, pose) in poses.iter().enumerate() {
+            let (loc, transformation) = resolved[&(idx as u32)];
+            assert_eq!(transformation, pose.1);
+            assert!(is_close(&loc, &pose.0, 2 * config.tolerance));
+        }
+    }
 }
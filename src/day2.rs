@@ -1,63 +1,211 @@
+use anyhow::{anyhow, bail};
+
 use crate::{answer, common::Answer};
 
 pub const ANSWER: Answer = answer!(150, 900);
 
+#[derive(Debug, PartialEq)]
 enum Command {
     Forward(isize),
+    Back(isize),
     Down(isize),
     Up(isize),
+    ResetAim,
+    /// Commands between `repeat N` and `end`, executed N times
+    Repeat(usize, Vec<Command>),
 }
 
-fn read_command(line: &String) -> Command {
-    let (a, b) = line.split_once(' ').unwrap();
-    let amount: isize = b.parse().unwrap();
-    match a {
-        "forward" => Command::Forward(amount),
-        "down" => Command::Down(amount),
-        "up" => Command::Up(amount),
-        _ => panic!("bad command"),
+/// Parse commands until `end` of the block opened at the given line or the end of input
+fn read_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    opened_at: Option<usize>,
+) -> anyhow::Result<Vec<Command>> {
+    let mut commands = vec![];
+    while let Some((line_no, line)) = lines.next() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let amount = || -> anyhow::Result<isize> {
+            match words[..] {
+                [_, amount] => amount
+                    .parse()
+                    .map_err(|_| anyhow!("line {}: bad amount {:?}", line_no, amount)),
+                _ => bail!("line {}: {} needs an amount", line_no, words[0]),
+            }
+        };
+        let command = match words.first() {
+            None => continue,
+            Some(&"forward") => Command::Forward(amount()?),
+            Some(&"back") => Command::Back(amount()?),
+            Some(&"down") => Command::Down(amount()?),
+            Some(&"up") => Command::Up(amount()?),
+            Some(&"reset-aim") if words.len() == 1 => Command::ResetAim,
+            Some(&"repeat") => {
+                let count = amount()?;
+                if count < 0 {
+                    bail!("line {}: negative repeat count", line_no);
+                }
+                Command::Repeat(count as usize, read_block(lines, Some(line_no))?)
+            }
+            Some(&"end") if opened_at.is_some() && words.len() == 1 => return Ok(commands),
+            Some(&"end") if opened_at.is_none() => bail!("line {}: end without repeat", line_no),
+            Some(_) => bail!("line {}: bad command {:?}", line_no, line),
+        };
+        commands.push(command);
     }
+    if let Some(line_no) = opened_at {
+        bail!("line {}: missing end of repeat block", line_no);
+    }
+    Ok(commands)
 }
 
-fn read_commands(lines: Vec<String>) -> Vec<Command> {
-    lines.iter().map(read_command).collect()
+fn read_commands(lines: &[String]) -> anyhow::Result<Vec<Command>> {
+    let mut numbered = lines.iter().enumerate().map(|(i, l)| (i + 1, l.as_str()));
+    read_block(&mut numbered, None)
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
-    let commands = read_commands(lines);
-    let horizontal: isize = commands
-        .iter()
-        .map(|c| match c {
-            Command::Forward(amount) => *amount as isize,
-            _ => 0,
-        })
-        .sum();
-    let vertical: isize = commands
-        .iter()
-        .map(|c| match c {
-            Command::Up(amount) => -*amount,
-            Command::Down(amount) => *amount,
-            _ => 0,
-        })
-        .sum();
-    println!("h={} v={}", horizontal, vertical);
-    (horizontal * vertical).try_into().unwrap()
+/// How `up` and `down` are interpreted
+#[derive(Clone, Copy, Debug)]
+enum Semantics {
+    /// up and down change the depth
+    Direct,
+    /// up and down change the aim, moving forward dives along it
+    Aim,
 }
 
-pub fn process_b(lines: Vec<String>) -> u64 {
-    let commands = read_commands(lines);
-    let mut horizontal: isize = 0;
-    let mut vertical: isize = 0;
-    let mut aim: isize = 0;
-    for command in commands {
-        match command {
-            Command::Forward(amount) => {
-                horizontal += amount;
-                vertical += aim * amount;
+#[derive(Debug, Default)]
+struct Submarine {
+    position: isize,
+    depth: isize,
+    aim: isize,
+}
+
+impl Submarine {
+    fn execute(&mut self, command: &Command, semantics: Semantics) {
+        match (command, semantics) {
+            (Command::Forward(amount), Semantics::Direct) => self.position += amount,
+            (Command::Back(amount), Semantics::Direct) => self.position -= amount,
+            (Command::Down(amount), Semantics::Direct) => self.depth += amount,
+            (Command::Up(amount), Semantics::Direct) => self.depth -= amount,
+            (Command::Forward(amount), Semantics::Aim) => {
+                self.position += amount;
+                self.depth += self.aim * amount;
+            }
+            (Command::Back(amount), Semantics::Aim) => {
+                self.position -= amount;
+                self.depth -= self.aim * amount;
+            }
+            (Command::Down(amount), Semantics::Aim) => self.aim += amount,
+            (Command::Up(amount), Semantics::Aim) => self.aim -= amount,
+            (Command::ResetAim, _) => self.aim = 0,
+            (Command::Repeat(count, block), _) => {
+                for _ in 0..*count {
+                    self.run(block, semantics);
+                }
             }
-            Command::Up(amount) => aim -= amount,
-            Command::Down(amount) => aim += amount,
         }
     }
-    (horizontal * vertical).try_into().unwrap()
+
+    /// Product of position and depth, which must not be negative
+    fn answer(&self) -> anyhow::Result<u64> {
+        let product = self
+            .position
+            .checked_mul(self.depth)
+            .ok_or_else(|| anyhow!("{:?} overflows the answer", self))?;
+        u64::try_from(product).map_err(|_| anyhow!("{:?} gives negative answer {}", self, product))
+    }
+
+    fn run(&mut self, commands: &[Command], semantics: Semantics) {
+        for command in commands {
+            self.execute(command, semantics);
+        }
+    }
+}
+
+fn navigate(lines: Vec<String>, semantics: Semantics) -> u64 {
+    let commands = read_commands(&lines).unwrap_or_else(|e| panic!("{}", e));
+    let mut submarine = Submarine::default();
+    submarine.run(&commands, semantics);
+    println!("{:?} with {:?}", submarine, semantics);
+    submarine.answer().unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    navigate(lines, Semantics::Direct)
+}
+
+pub fn process_b(lines: Vec<String>) -> u64 {
+    navigate(lines, Semantics::Aim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_lines(sample: &str) -> Vec<String> {
+        sample.lines().map(String::from).collect()
+    }
+
+    static SAMPLE: &str = "forward 5
+down 5
+forward 8
+up 3
+down 8
+forward 2";
+
+    #[test]
+    fn test_sample() {
+        assert_eq!(process_a(to_lines(SAMPLE)), 150);
+        assert_eq!(process_b(to_lines(SAMPLE)), 900);
+    }
+
+    #[test]
+    fn test_extended_commands() {
+        let program = "down 2
+repeat 3
+  forward 4
+  repeat 2
+    back 1
+  end
+end
+reset-aim
+forward 10";
+        let commands = read_commands(&to_lines(program)).unwrap();
+        assert_eq!(commands.len(), 4);
+
+        let mut submarine = Submarine::default();
+        submarine.run(&commands, Semantics::Aim);
+        assert_eq!(
+            (submarine.position, submarine.depth, submarine.aim),
+            (16, 12, 0)
+        );
+
+        let mut submarine = Submarine::default();
+        submarine.run(&commands, Semantics::Direct);
+        assert_eq!((submarine.position, submarine.depth), (16, 2));
+
+        let mut submarine = Submarine::default();
+        submarine.run(
+            &read_commands(&to_lines("back 5\ndown 3")).unwrap(),
+            Semantics::Direct,
+        );
+        assert_eq!(
+            submarine.answer().unwrap_err().to_string(),
+            "Submarine { position: -5, depth: 3, aim: 0 } gives negative answer -15"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |program: &str| read_commands(&to_lines(program)).unwrap_err().to_string();
+        assert_eq!(
+            error("forward 1\nsideways 2"),
+            "line 2: bad command \"sideways 2\""
+        );
+        assert_eq!(error("up 1\n\ndown x"), "line 3: bad amount \"x\"");
+        assert_eq!(error("forward"), "line 1: forward needs an amount");
+        assert_eq!(error("end"), "line 1: end without repeat");
+        assert_eq!(
+            error("up 1\nrepeat 2\nrepeat 3\nup 1\nend"),
+            "line 2: missing end of repeat block"
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use itertools::Itertools;
 use regex::Regex;
 use std::collections::HashSet;
 
@@ -11,45 +12,66 @@ pub const ANSWER: Answer = answer!(39, 2758514936282235);
 
 /* Impl */
 
-type Pos = (isize, isize, isize);
+type Pos = Vec<isize>;
 
 type Range = (isize, isize);
 
-type Cube = (Range, Range, Range);
+/// N-dimensional box, one inclusive range per axis
+type Cube = Vec<Range>;
 
 #[derive(Debug, PartialEq)]
 struct Instruction {
     on: bool,
-    xrange: Range,
-    yrange: Range,
-    zrange: Range,
+    cube: Cube,
 }
 
-fn parse_insruction(line: &str) -> Instruction {
-    let re = Regex::new(
-        r"(on|off) x=([0-9-]+)\.\.([0-9-]+),y=([0-9-]+)\.\.([0-9-]+),z=([0-9-]+)\.\.([0-9-]+)",
-    )
-    .unwrap();
-    let cap = re.captures(line).unwrap();
-    Instruction {
-        on: cap.get(1).unwrap().as_str() == "on",
-        xrange: (
-            cap.get(2).unwrap().as_str().parse::<isize>().unwrap(),
-            cap.get(3).unwrap().as_str().parse::<isize>().unwrap(),
-        ),
-        yrange: (
-            cap.get(4).unwrap().as_str().parse::<isize>().unwrap(),
-            cap.get(5).unwrap().as_str().parse::<isize>().unwrap(),
-        ),
-        zrange: (
-            cap.get(6).unwrap().as_str().parse::<isize>().unwrap(),
-            cap.get(7).unwrap().as_str().parse::<isize>().unwrap(),
-        ),
+impl Instruction {
+    fn dimensions(&self) -> usize {
+        self.cube.len()
     }
 }
 
+fn parse_insruction(line: &str) -> Instruction {
+    let re_switch = Regex::new(r"^(on|off) ").unwrap();
+    let re_axis = Regex::new(r"([a-z])=([0-9-]+)\.\.([0-9-]+)").unwrap();
+    let on = re_switch.captures(line).unwrap().get(1).unwrap().as_str() == "on";
+    let cube: Cube = re_axis
+        .captures_iter(line)
+        .map(|cap| {
+            (
+                cap.get(2).unwrap().as_str().parse::<isize>().unwrap(),
+                cap.get(3).unwrap().as_str().parse::<isize>().unwrap(),
+            )
+        })
+        .collect();
+    assert!(!cube.is_empty(), "No axes in instruction '{}'", line);
+    Instruction { on, cube }
+}
+
 fn read_instructions(lines: Vec<String>) -> Vec<Instruction> {
-    lines.iter().map(|s| parse_insruction(s)).collect()
+    let instructions: Vec<Instruction> = lines
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| parse_insruction(s))
+        .collect();
+    if let Some(first) = instructions.first() {
+        assert!(
+            instructions
+                .iter()
+                .all(|i| i.dimensions() == first.dimensions()),
+            "Instructions have different number of dimensions"
+        );
+    }
+    instructions
+}
+
+/// Iterate over all points of a cube clamped to `[pmin, pmax]` on every axis
+fn clamped_points(cube: &Cube, pmin: isize, pmax: isize) -> impl Iterator<Item = Pos> {
+    cube.iter()
+        .map(|range| range.0.max(pmin)..=range.1.min(pmax))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .multi_cartesian_product()
 }
 
 fn resolve_a(instructions: Vec<Instruction>) -> u64 {
@@ -59,33 +81,20 @@ fn resolve_a(instructions: Vec<Instruction>) -> u64 {
     let mut reactor: HashSet<Pos> = HashSet::new();
     let mut counter: usize = 0;
     for instruction in instructions {
-        for x in instruction.xrange.0.max(pmin)..=instruction.xrange.1.min(pmax) {
-            for y in instruction.yrange.0.max(pmin)..=instruction.yrange.1.min(pmax) {
-                for z in instruction.zrange.0.max(pmin)..=instruction.zrange.1.min(pmax) {
-                    if instruction.on {
-                        reactor.insert((x, y, z));
-                    } else {
-                        reactor.remove(&(x, y, z));
-                    }
-                    counter += 1;
-                }
+        for pos in clamped_points(&instruction.cube, pmin, pmax) {
+            if instruction.on {
+                reactor.insert(pos);
+            } else {
+                reactor.remove(&pos);
             }
+            counter += 1;
         }
     }
     println!("Iterated {} steps", counter);
     println!("Filled reactor hash with {} items", reactor.len());
 
-    let mut counter = 0u64;
-    for x in pmin..=pmax {
-        for y in pmin..=pmax {
-            for z in pmin..=pmax {
-                if reactor.contains(&(x, y, z)) {
-                    counter += 1;
-                }
-            }
-        }
-    }
-    counter as u64
+    // only points within bounds were inserted
+    reactor.len() as u64
 }
 
 fn does_intersect(range1: &Range, range2: &Range) -> bool {
@@ -106,38 +115,50 @@ fn intersection(range1: &Range, range2: &Range) -> Range {
     (l, r)
 }
 
+fn does_cube_intersect(cube1: &Cube, cube2: &Cube) -> bool {
+    assert_eq!(cube1.len(), cube2.len());
+    cube1
+        .iter()
+        .zip(cube2.iter())
+        .all(|(range1, range2)| does_intersect(range1, range2))
+}
+
 fn _cube_intersection_volume(cube1: &Cube, cube2: &Cube) -> Option<usize> {
-    let intersected = does_intersect(&cube1.0, &cube2.0)
-        && does_intersect(&cube1.1, &cube2.1)
-        && does_intersect(&cube1.2, &cube2.2);
-    if !intersected {
+    if !does_cube_intersect(cube1, cube2) {
         return None;
     }
-    let volume = intersection_size(&cube1.0, &cube2.0)
-        * intersection_size(&cube1.1, &cube2.1)
-        * intersection_size(&cube1.2, &cube2.2);
+    let volume = cube1
+        .iter()
+        .zip(cube2.iter())
+        .map(|(range1, range2)| intersection_size(range1, range2))
+        .product();
     Some(volume)
 }
 
 fn cube_intersection(cube1: &Cube, cube2: &Cube) -> Option<Cube> {
-    let intersected = does_intersect(&cube1.0, &cube2.0)
-        && does_intersect(&cube1.1, &cube2.1)
-        && does_intersect(&cube1.2, &cube2.2);
-    if !intersected {
+    if !does_cube_intersect(cube1, cube2) {
         return None;
     }
-    Some((
-        intersection(&cube1.0, &cube2.0),
-        intersection(&cube1.1, &cube2.1),
-        intersection(&cube1.2, &cube2.2),
-    ))
+    Some(
+        cube1
+            .iter()
+            .zip(cube2.iter())
+            .map(|(range1, range2)| intersection(range1, range2))
+            .collect(),
+    )
+}
+
+fn cube_contains(cube: &Cube, pos: &Pos) -> bool {
+    assert_eq!(cube.len(), pos.len());
+    cube.iter()
+        .zip(pos.iter())
+        .all(|(range, coord)| range.0 <= *coord && *coord <= range.1)
 }
 
 fn cube_volume(cube: &Cube) -> usize {
-    usize::try_from(
-        (1 + cube.0 .1 - cube.0 .0) * (1 + cube.1 .1 - cube.1 .0) * (1 + cube.2 .1 - cube.2 .0),
-    )
-    .unwrap()
+    cube.iter()
+        .map(|range| usize::try_from(1 + range.1 - range.0).unwrap())
+        .product()
 }
 
 fn _resolve_b_volume(instructions: &mut Vec<Instruction>) -> u64 {
@@ -153,8 +174,7 @@ fn _resolve_b_volume(instructions: &mut Vec<Instruction>) -> u64 {
     for idx in 0..count {
         let instruction = &instructions[idx];
         if instruction.on {
-            let local_volume =
-                cube_volume(&(instruction.xrange, instruction.yrange, instruction.zrange)) as isize;
+            let local_volume = cube_volume(&instruction.cube) as isize;
             let (res, overflow) = volume.overflowing_add(local_volume);
             assert!(!overflow);
             volume = res;
@@ -174,18 +194,7 @@ fn _resolve_b_volume(instructions: &mut Vec<Instruction>) -> u64 {
         for idx2 in 0..idx {
             let instruction1 = &instructions[idx];
             let instruction2 = &instructions[idx2];
-            if let Some(intersected) = cube_intersection(
-                &(
-                    instruction1.xrange,
-                    instruction1.yrange,
-                    instruction1.zrange,
-                ),
-                &(
-                    instruction2.xrange,
-                    instruction2.yrange,
-                    instruction2.zrange,
-                ),
-            ) {
+            if let Some(intersected) = cube_intersection(&instruction1.cube, &instruction2.cube) {
                 intersecteds.push(intersected);
                 ons.push(instruction2.on);
             }
@@ -230,64 +239,107 @@ fn _resolve_b_volume(instructions: &mut Vec<Instruction>) -> u64 {
     u64::try_from(volume).unwrap()
 }
 
-fn resolve_b_iterative(instructions: &mut Vec<Instruction>) -> u64 {
-    // Works. Iterative solution.
-    // Inspired by
-    // https://www.reddit.com/r/adventofcode/comments/rlxhmg/comment/hqxczc4
-    let count = instructions.len();
+/// Reactor state after a reboot, kept as signed cubes to answer queries
+struct Reactor {
+    instructions: Vec<Instruction>,
+    cubes_add: Vec<Cube>,
+    cubes_sub: Vec<Cube>,
+}
 
-    let mut cubes_add: Vec<Cube> = vec![];
-    let mut cubes_sub: Vec<Cube> = vec![];
+impl Reactor {
+    fn reboot(instructions: Vec<Instruction>) -> Reactor {
+        // Works. Iterative solution.
+        // Inspired by
+        // https://www.reddit.com/r/adventofcode/comments/rlxhmg/comment/hqxczc4
+        let mut cubes_add: Vec<Cube> = vec![];
+        let mut cubes_sub: Vec<Cube> = vec![];
 
-    for idx in 0..count {
-        let instruction = &instructions[idx];
-        let cur_cube = (instruction.xrange, instruction.yrange, instruction.zrange);
+        for (idx, instruction) in instructions.iter().enumerate() {
+            let cur_cube = &instruction.cube;
 
-        let mut new_cubes_add: Vec<Cube> = vec![];
-        let mut new_cubes_sub: Vec<Cube> = vec![];
-        for cube in &cubes_add {
-            if let Some(intersected) = cube_intersection(&cur_cube, &cube) {
-                new_cubes_sub.push(intersected);
+            let mut new_cubes_add: Vec<Cube> = vec![];
+            let mut new_cubes_sub: Vec<Cube> = vec![];
+            for cube in &cubes_add {
+                if let Some(intersected) = cube_intersection(cur_cube, cube) {
+                    new_cubes_sub.push(intersected);
+                }
             }
-        }
-        for cube in &cubes_sub {
-            if let Some(intersected) = cube_intersection(&cur_cube, cube) {
-                new_cubes_add.push(intersected);
+            for cube in &cubes_sub {
+                if let Some(intersected) = cube_intersection(cur_cube, cube) {
+                    new_cubes_add.push(intersected);
+                }
             }
-        }
-
-        // do not add 'off' cube to `cubes_add` but treat its intersections (two loops below)
-        if instruction.on {
-            cubes_add.push(cur_cube);
-        }
-
-        cubes_add.extend(new_cubes_add);
-        cubes_sub.extend(new_cubes_sub);
 
-        if false {
-            println!(
-                "After instruction {} cubes to add {}, to sub {}",
-                idx + 1,
-                cubes_add.len(),
-                cubes_sub.len()
-            );
-            for cube in &cubes_add {
-                println!(" add: {:?}", cube);
+            // do not add 'off' cube to `cubes_add` but treat its intersections (two loops below)
+            if instruction.on {
+                cubes_add.push(cur_cube.clone());
             }
-            for cube in &cubes_sub {
-                println!(" sub: {:?}", cube);
+
+            cubes_add.extend(new_cubes_add);
+            cubes_sub.extend(new_cubes_sub);
+
+            if false {
+                println!(
+                    "After instruction {} cubes to add {}, to sub {}",
+                    idx + 1,
+                    cubes_add.len(),
+                    cubes_sub.len()
+                );
+                for cube in &cubes_add {
+                    println!(" add: {:?}", cube);
+                }
+                for cube in &cubes_sub {
+                    println!(" sub: {:?}", cube);
+                }
             }
         }
+        Reactor {
+            instructions,
+            cubes_add,
+            cubes_sub,
+        }
     }
-    let volume: isize = cubes_add
-        .iter()
-        .map(|cube| cube_volume(cube) as isize)
-        .sum::<isize>()
-        - cubes_sub
+
+    /// Total number of lit cubes
+    fn lit_count(&self) -> u64 {
+        let volume: isize = self
+            .cubes_add
             .iter()
             .map(|cube| cube_volume(cube) as isize)
-            .sum::<isize>();
-    u64::try_from(volume).unwrap()
+            .sum::<isize>()
+            - self
+                .cubes_sub
+                .iter()
+                .map(|cube| cube_volume(cube) as isize)
+                .sum::<isize>();
+        u64::try_from(volume).unwrap()
+    }
+
+    /// Number of lit cubes inside the `query` cuboid
+    fn lit_count_within(&self, query: &Cube) -> u64 {
+        let volume_within = |cubes: &Vec<Cube>| -> isize {
+            cubes
+                .iter()
+                .filter_map(|cube| cube_intersection(query, cube))
+                .map(|cube| cube_volume(&cube) as isize)
+                .sum()
+        };
+        u64::try_from(volume_within(&self.cubes_add) - volume_within(&self.cubes_sub)).unwrap()
+    }
+
+    /// Index and the instruction that was the last to switch `pos`
+    fn last_instruction(&self, pos: &Pos) -> Option<(usize, &Instruction)> {
+        self.instructions
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, instruction)| cube_contains(&instruction.cube, pos))
+    }
+
+    fn is_on(&self, pos: &Pos) -> bool {
+        self.last_instruction(pos)
+            .is_some_and(|(_, instruction)| instruction.on)
+    }
 }
 
 fn _all_intersections(cubes: &Vec<Cube>) -> Vec<Cube> {
@@ -321,8 +373,7 @@ fn _resolve_b_hier(instructions: &mut Vec<Instruction>) -> u64 {
     levels.push(vec![]);
     for idx in 0..count {
         let instruction = &instructions[idx];
-        let cur_cube = (instruction.xrange, instruction.yrange, instruction.zrange);
-        levels[0].push(cur_cube);
+        levels[0].push(instruction.cube.clone());
     }
     println!("At level 0 there are {} items", &levels[0].len());
 
@@ -345,7 +396,7 @@ fn _resolve_b_hier(instructions: &mut Vec<Instruction>) -> u64 {
                 .iter()
                 .zip(instructions.iter())
                 .filter(|(_cube, instr)| instr.on)
-                .map(|(cube, _instr)| *cube)
+                .map(|(cube, _instr)| cube.clone())
                 .collect()
         } else {
             levels[level].clone()
@@ -367,61 +418,35 @@ fn _resolve_b(instructions: Vec<Instruction>) -> u64 {
     let pmin: isize = -50;
     let pmax: isize = 50;
 
-    let xmin: isize = instructions.iter().map(|i| i.xrange.0).min().unwrap();
-    let xmax: isize = instructions.iter().map(|i| i.xrange.1).max().unwrap();
-    let ymin: isize = instructions.iter().map(|i| i.yrange.0).min().unwrap();
-    let ymax: isize = instructions.iter().map(|i| i.yrange.1).max().unwrap();
-    let zmin: isize = instructions.iter().map(|i| i.zrange.0).min().unwrap();
-    let zmax: isize = instructions.iter().map(|i| i.zrange.1).max().unwrap();
-
-    //let xcount : usize = (xmax-xmin+1) as usize;
-    //let ycount : usize = (ymax-ymin+1) as usize;
-    //let zcount : usize = (zmax-zmin+1) as usize;
+    let dimensions = instructions[0].dimensions();
+    let extents: Vec<isize> = (0..dimensions)
+        .map(|axis| {
+            let amin: isize = instructions.iter().map(|i| i.cube[axis].0).min().unwrap();
+            let amax: isize = instructions.iter().map(|i| i.cube[axis].1).max().unwrap();
+            amax - amin
+        })
+        .collect();
 
-    //let mut reactor : Vec<bool> = vec![false; xcount*ycount*zcount];
     let reactor: HashSet<Pos> = HashSet::new();
     let mut counter: usize = 0;
-    println!("x {}, y {}, z {}", xmax - xmin, ymax - ymin, zmax - zmin);
+    println!("extents {:?}", extents);
     for instruction in instructions {
-        //for x in instruction.xrange.0.max(pmin)..=instruction.xrange.1.min(pmax) {
-        //for y in instruction.yrange.0.max(pmin)..=instruction.yrange.1.min(pmax) {
-        //for z in instruction.zrange.0.max(pmin)..=instruction.zrange.1.min(pmax) {
-        for _x in instruction.xrange.0..=instruction.xrange.1 {
-            for _y in instruction.yrange.0..=instruction.yrange.1 {
-                for _z in instruction.zrange.0..=instruction.zrange.1 {
-                    //let pos =
-                    //((x - xmin) as usize) * ycount * zcount
-                    //+ ((y - ymin) as usize) * zcount
-                    //+ (z - zmin) as usize;
-                    //if x < pmin || y < pmin || z < pmin || x > pmax || y > pmax || z > pmax {
-                    //continue;
-                    //}
-                    //if instruction.on {
-                    //reactor.insert((x,y,z));
-                    //} else {
-                    //reactor.remove(&(x,y,z));
-                    //}
-                    counter += 1;
-                }
-            }
+        for _pos in clamped_points(&instruction.cube, isize::MIN, isize::MAX) {
+            //if instruction.on {
+            //reactor.insert(pos);
+            //} else {
+            //reactor.remove(&pos);
+            //}
+            counter += 1;
         }
     }
     println!("Iterated {} steps", counter);
     println!("Filled reactor hash with {} items", reactor.len());
 
-    let mut counter = 0u64;
-    for x in pmin..=pmax {
-        for y in pmin..=pmax {
-            for z in pmin..=pmax {
-                //if x < pmin || y < pmin || z < pmin || x > pmax || y > pmax || z > pmax {
-                //continue;
-                //}
-                if reactor.contains(&(x, y, z)) {
-                    counter += 1;
-                }
-            }
-        }
-    }
+    let bounds: Cube = vec![(pmin, pmax); dimensions];
+    let counter = clamped_points(&bounds, pmin, pmax)
+        .filter(|pos| reactor.contains(pos))
+        .count();
     counter as u64
 }
 
@@ -429,6 +454,98 @@ pub fn process_a(lines: Vec<String>) -> u64 {
     resolve_a(read_instructions(lines))
 }
 
+fn describe_reactor(reactor: &Reactor) {
+    let dimensions = reactor.instructions.first().map_or(0, |i| i.dimensions());
+    let region: Cube = vec![(-50, 50); dimensions];
+    println!(
+        "Lit inside initialization region: {}",
+        reactor.lit_count_within(&region)
+    );
+    let origin: Pos = vec![0; dimensions];
+    match reactor.last_instruction(&origin) {
+        Some((idx, instruction)) => println!(
+            "Origin is {}, last switched by instruction {} {:?}",
+            if reactor.is_on(&origin) { "on" } else { "off" },
+            idx + 1,
+            instruction
+        ),
+        None => println!("Origin is off, never switched"),
+    }
+}
+
 pub fn process_b(lines: Vec<String>) -> u64 {
-    resolve_b_iterative(&mut read_instructions(lines))
+    let reactor = Reactor::reboot(read_instructions(lines));
+    describe_reactor(&reactor);
+    reactor.lit_count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_sample(sample: &str) -> Vec<Instruction> {
+        read_instructions(sample.split('\n').map(String::from).collect())
+    }
+
+    #[test]
+    fn test_parse_dimensions() {
+        let instructions = read_sample("on x=1..2,y=-3..4\noff x=0..0,y=1..1");
+        assert_eq!(
+            instructions[0],
+            Instruction {
+                on: true,
+                cube: vec![(1, 2), (-3, 4)]
+            }
+        );
+        assert_eq!(instructions[1].dimensions(), 2);
+        let instructions = read_sample("on x=1..2,y=-3..4,z=5..6,w=-8..-7");
+        assert_eq!(
+            instructions[0].cube,
+            vec![(1, 2), (-3, 4), (5, 6), (-8, -7)]
+        );
+    }
+
+    #[test]
+    fn test_2d() {
+        let sample = "on x=0..3,y=0..3\noff x=1..2,y=1..2\non x=2..5,y=2..2";
+        assert_eq!(resolve_a(read_sample(sample)), 15);
+        assert_eq!(Reactor::reboot(read_sample(sample)).lit_count(), 15);
+    }
+
+    #[test]
+    fn test_4d() {
+        let sample = "on x=0..2,y=0..2,z=0..2,w=0..2\n\
+                      off x=1..1,y=1..1,z=1..1,w=0..2\n\
+                      on x=2..3,y=2..2,z=2..2,w=2..2";
+        assert_eq!(resolve_a(read_sample(sample)), 79);
+        assert_eq!(Reactor::reboot(read_sample(sample)).lit_count(), 79);
+    }
+
+    #[test]
+    fn test_reactor_queries() {
+        let sample = "on x=10..12,y=10..12,z=10..12\n\
+                      on x=11..13,y=11..13,z=11..13\n\
+                      off x=9..11,y=9..11,z=9..11\n\
+                      on x=10..10,y=10..10,z=10..10";
+        let reactor = Reactor::reboot(read_sample(sample));
+        assert_eq!(reactor.lit_count(), 39);
+
+        assert!(reactor.is_on(&vec![10, 10, 10]));
+        assert!(!reactor.is_on(&vec![11, 11, 11]));
+        assert!(reactor.is_on(&vec![13, 13, 13]));
+        assert!(!reactor.is_on(&vec![0, 0, 0]));
+
+        assert_eq!(reactor.last_instruction(&vec![10, 10, 10]).unwrap().0, 3);
+        assert_eq!(reactor.last_instruction(&vec![11, 11, 11]).unwrap().0, 2);
+        assert_eq!(reactor.last_instruction(&vec![13, 12, 11]).unwrap().0, 1);
+        assert!(reactor.last_instruction(&vec![0, 0, 0]).is_none());
+
+        assert_eq!(reactor.lit_count_within(&vec![(0, 100); 3]), 39);
+        assert_eq!(reactor.lit_count_within(&vec![(13, 13); 3]), 1);
+        assert_eq!(reactor.lit_count_within(&vec![(9, 11); 3]), 1);
+        assert_eq!(
+            reactor.lit_count_within(&vec![(12, 13), (12, 13), (10, 13)]),
+            13
+        );
+    }
 }
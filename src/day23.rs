@@ -1,9 +1,10 @@
 use priority_queue::PriorityQueue;
 use regex::Regex;
 use std::cmp::Reverse;
+use std::collections::HashMap;
 use std::fmt;
 
-use crate::{answer, common::Answer};
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(12521, 44169);
 
@@ -59,11 +60,9 @@ fn read_problem(lines: Vec<String>) -> State {
         &mut Vec::new(),
         &mut Vec::new(),
     ];
-    let re = Regex::new(r"([ABCD\.])").unwrap();
     for _ in 0..lines.len() - 2 {
         let line = it.next().unwrap();
-        for (idx, m) in re.find_iter(line).enumerate() {
-            let a: Amphipod = m.as_str().chars().next().unwrap();
+        for (idx, a) in parse_row(line).into_iter().enumerate() {
             mut_rooms[idx].push(a);
         }
     }
@@ -71,6 +70,39 @@ fn read_problem(lines: Vec<String>) -> State {
     State { rooms, hallway }
 }
 
+/// Parse amphipods of one burrow row like `#D#C#B#A#`
+fn parse_row(line: &str) -> Vec<Amphipod> {
+    let re = Regex::new(r"([ABCD\.])").unwrap();
+    re.find_iter(line)
+        .map(|m| m.as_str().chars().next().unwrap())
+        .collect()
+}
+
+/// Rows folded out of the part A diagram
+const UNFOLDED_ROWS: [&str; 2] = ["#D#C#B#A#", "#D#B#A#C#"];
+
+/// Rows from `--rows=#D#C#B#A#/#D#B#A#C#`, the folded rows by default
+fn read_rows() -> Vec<&'static str> {
+    common::option("rows").map_or(UNFOLDED_ROWS.to_vec(), |rows| rows.split('/').collect())
+}
+
+/// Insert `rows` (top to bottom) between the top and the rest of the rooms
+fn unfold(state: &State, rows: &[&str]) -> State {
+    let mut rooms = state.rooms.clone();
+    let top = state.height() - 1;
+    for row in rows {
+        let amphipods = parse_row(row);
+        assert_eq!(amphipods.len(), 4, "Wrong row {}", row);
+        for (room, a) in rooms.iter_mut().zip(amphipods) {
+            room.insert(top, a);
+        }
+    }
+    State {
+        rooms,
+        hallway: state.hallway,
+    }
+}
+
 fn is_final(state: &State) -> bool {
     state.hallway.iter().all(|r| *r == EMPTY)
         && (0..state.height())
@@ -215,48 +247,127 @@ fn derive_states(state: &State) -> Vec<(State, usize)> {
     result
 }
 
-fn resolve_pq(init_state: State) -> u64 {
+/// Optimal organization plan: states from the initial one with energy spent on each move
+struct Solution {
+    steps: Vec<(State, usize)>,
+}
+
+impl Solution {
+    fn total_cost(&self) -> usize {
+        self.steps.iter().map(|(_, move_cost)| move_cost).sum()
+    }
+}
+
+impl fmt::Display for Solution {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut total_cost: usize = 0;
+        for (idx, (state, move_cost)) in self.steps.iter().enumerate() {
+            total_cost += move_cost;
+            if idx == 0 {
+                writeln!(fmt, "Init")?;
+            } else {
+                writeln!(
+                    fmt,
+                    "Move {}, energy {} (total {})",
+                    idx, move_cost, total_cost
+                )?;
+            }
+            writeln!(fmt, "{}", state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lower bound of energy to move every amphipod into its home room ignoring blocking
+fn heuristic(state: &State) -> usize {
+    let mut energy: usize = 0;
+    for (hpos, amphipod) in state.hallway.iter().enumerate() {
+        if *amphipod != EMPTY {
+            let hpos_home = 2 + home_room(*amphipod) * 2;
+            energy += cost(*amphipod) * (hpos.abs_diff(hpos_home) + 1);
+        }
+    }
+    for room in 0..4 {
+        for floor in 0..state.height() {
+            let amphipod = state.rooms[room][floor];
+            if amphipod != EMPTY && home_room(amphipod) != room {
+                let moves_up = state.height() - floor;
+                let moves_across = (room * 2).abs_diff(home_room(amphipod) * 2);
+                energy += cost(amphipod) * (moves_up + moves_across + 1);
+            }
+        }
+    }
+    energy
+}
+
+fn resolve_pq(init_state: State) -> Option<Solution> {
     println!("Init\n{}", init_state);
 
-    let mut best_cost: usize = usize::MAX;
+    // A* search ordered by cost so far plus heuristic
     let mut pq: PriorityQueue<State, Reverse<usize>> = PriorityQueue::new();
+    // lowest known cost of a state and a move to it
+    let mut costs: HashMap<State, usize> = HashMap::new();
+    let mut parents: HashMap<State, (State, usize)> = HashMap::new();
+    let mut explored: usize = 0;
 
-    pq.push(init_state, Reverse(0));
+    costs.insert(init_state.clone(), 0);
+    pq.push(init_state.clone(), Reverse(heuristic(&init_state)));
 
-    while let Some((state, rcost)) = pq.pop() {
-        let cost: usize = rcost.0; // get from Reverse wrapper
+    let mut final_state: Option<State> = None;
+    while let Some((state, _)) = pq.pop() {
+        explored += 1;
 
+        // heuristic is consistent so the first final state is the best one
         if is_final(&state) {
-            best_cost = best_cost.min(cost);
-            continue;
+            final_state = Some(state);
+            break;
         }
 
+        let cost: usize = costs[&state];
         for (new_state, move_cost) in derive_states(&state) {
-            // Prune tree starting from this state
-            if cost + move_cost >= best_cost {
+            let new_cost = cost + move_cost;
+            if costs
+                .get(&new_state)
+                .is_some_and(|existing_cost| new_cost >= *existing_cost)
+            {
                 continue;
             }
-            if let Some(existing_cost) = pq.get_priority(&new_state) {
-                if cost + move_cost < existing_cost.0 {
-                    // Update to lower cost for existing state
-                    pq.push(new_state, Reverse(cost + move_cost));
-                }
-            } else {
-                // Insert new state
-                pq.push(new_state, Reverse(cost + move_cost));
-            }
+            // Insert new state or update to lower cost for existing state
+            let priority = new_cost + heuristic(&new_state);
+            costs.insert(new_state.clone(), new_cost);
+            parents.insert(new_state.clone(), (state.clone(), move_cost));
+            pq.push(new_state, Reverse(priority));
         }
     }
+    println!(
+        "Explored {} states, discovered {} states",
+        explored,
+        costs.len()
+    );
+
+    let mut steps: Vec<(State, usize)> = vec![];
+    let mut state = final_state?;
+    while let Some((parent, move_cost)) = parents.remove(&state) {
+        steps.push((state, move_cost));
+        state = parent;
+    }
+    steps.push((state, 0));
+    steps.reverse();
+    Some(Solution { steps })
+}
 
-    best_cost as u64
+fn solve(init_state: State) -> u64 {
+    let solution = resolve_pq(init_state).expect("No solution");
+    print!("{}", solution);
+    solution.total_cost() as u64
 }
 
 pub fn process_a(lines: Vec<String>) -> u64 {
-    resolve_pq(read_problem(lines))
+    solve(read_problem(lines))
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    process_a(lines)
+    solve(unfold(&read_problem(lines), &read_rows()))
 }
 
 #[cfg(test)]
@@ -409,4 +520,59 @@ mod tests {
             .find(|c| read_problem_case(CASE_7) == c.0)
             .is_none());
     }
+
+    static SAMPLE: &str = "
+#############
+#...........#
+###B#C#B#D###
+  #A#D#C#A#
+  #########
+";
+
+    static SAMPLE_UNFOLDED: &str = "
+#############
+#...........#
+###B#C#B#D###
+  #D#C#B#A#
+  #D#B#A#C#
+  #A#D#C#A#
+  #########
+";
+
+    #[test]
+    fn test_unfold() {
+        let state = read_problem_case(SAMPLE);
+        assert_eq!(unfold(&state, &[]), state);
+        let unfolded = unfold(&state, &UNFOLDED_ROWS);
+        assert_eq!(unfolded, read_problem_case(SAMPLE_UNFOLDED));
+        assert_eq!(unfolded.height(), 4);
+        let deeper = unfold(&unfolded, &["#A#B#C#D#"]);
+        assert_eq!(deeper.height(), 5);
+        assert_eq!(deeper.rooms[0], vec!['A', 'D', 'D', 'A', 'B']);
+    }
+
+    #[test]
+    fn test_solution_replay() {
+        let solution = resolve_pq(read_problem_case(CASE_6)).unwrap();
+        assert_eq!(solution.steps[0], (read_problem_case(CASE_6), 0));
+        assert!(is_final(&solution.steps.last().unwrap().0));
+        for pair in solution.steps.windows(2) {
+            assert!(derive_states(&pair[0].0).contains(&pair[1]));
+        }
+        assert_eq!(
+            solution.total_cost(),
+            solution.steps.iter().map(|step| step.1).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_heuristic() {
+        let solution = resolve_pq(read_problem_case(SAMPLE)).unwrap();
+        assert_eq!(solution.total_cost(), 12521);
+        assert_eq!(heuristic(&solution.steps.last().unwrap().0), 0);
+        for (idx, (state, _)) in solution.steps.iter().enumerate() {
+            let rest: usize = solution.steps[idx + 1..].iter().map(|step| step.1).sum();
+            assert!(heuristic(state) <= rest);
+        }
+    }
 }
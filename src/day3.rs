@@ -1,108 +1,253 @@
-use crate::{answer, common::Answer};
+use anyhow::bail;
+
+use crate::{
+    answer,
+    common::{Answer, BigUint},
+};
 
 pub const ANSWER: Answer = answer!(198, 230);
 
-pub fn process_a(lines: Vec<String>) -> u64 {
-    let mut zeroes = Vec::new();
-    let mut ones = Vec::new();
-    for line in lines {
-        if zeroes.is_empty() {
-            zeroes.resize(line.len(), 0);
-            ones.resize(line.len(), 0);
-        }
-        for (ind, c) in line.trim().chars().enumerate() {
-            match c {
-                '0' => zeroes[ind] += 1,
-                '1' => ones[ind] += 1,
-                _ => panic!(),
-            }
-        }
-        println!("{}", line);
+/// Diagnostic report parsed into integers of any width, sorted ascending
+struct Report {
+    width: usize,
+    values: Vec<BigUint>,
+}
+
+fn read_report(lines: &[String]) -> Report {
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    let width = lines.first().expect("Empty report").len();
+    let mut values: Vec<BigUint> = lines
+        .iter()
+        .enumerate()
+        .map(|(ind, line)| {
+            assert!(
+                line.len() == width,
+                "Line {} is not {} bits",
+                ind + 1,
+                width
+            );
+            BigUint::from_binary(line)
+                .unwrap_or_else(|| panic!("Line {} is not binary: {}", ind + 1, line))
+        })
+        .collect();
+    values.sort_unstable();
+    Report { width, values }
+}
+
+fn count_ones(values: &[BigUint], pos: usize) -> usize {
+    values.iter().filter(|v| v.bit(pos)).count()
+}
+
+/// Ones, zeroes and share of ones per column, most significant first
+fn print_bit_stats(report: &Report) {
+    let total = report.values.len();
+    println!("{} values of {} bits", total, report.width);
+    println!("  bit     ones   zeroes  ones%");
+    for pos in (0..report.width).rev() {
+        let ones = count_ones(&report.values, pos);
+        println!(
+            "  {:>3} {:>8} {:>8} {:>5.1}",
+            pos,
+            ones,
+            total - ones,
+            100.0 * ones as f64 / total as f64
+        );
     }
-    let mut gamma = String::new();
-    let mut epsilon = String::new();
-    for ind in 0..zeroes.len() {
-        if ones[ind] > zeroes[ind] {
-            gamma.push('1');
-            epsilon.push('0');
+}
+
+fn power_rates(report: &Report) -> (BigUint, BigUint) {
+    let mut gamma = BigUint::from_u64(0);
+    let mut epsilon = BigUint::from_u64(0);
+    for pos in 0..report.width {
+        if 2 * count_ones(&report.values, pos) > report.values.len() {
+            gamma.set_bit(pos);
         } else {
-            gamma.push('0');
-            epsilon.push('1');
+            epsilon.set_bit(pos);
         }
     }
-    let gamma_num = isize::from_str_radix(&gamma, 2).unwrap();
-    let epsilon_num = isize::from_str_radix(&epsilon, 2).unwrap();
-    println!(
-        "\ngamma: {}, {}, {}, {}",
-        gamma, gamma_num, epsilon, epsilon_num
-    );
-    (gamma_num * epsilon_num) as u64
+    (gamma, epsilon)
 }
 
-fn detect_sensor(input: Vec<String>, prefer_one: bool) -> Result<u32, &'static str> {
-    let mut data = input;
-    let n = data[0].len();
-    for i in 0..n {
-        let ones = data
-            .iter()
-            .filter(|x| x.chars().nth(i).unwrap() == '1')
-            .count();
-        let zeroes = data
-            .iter()
-            .filter(|x| x.chars().nth(i).unwrap() == '0')
-            .count();
-        let mut prefered;
-        if prefer_one {
-            if ones > zeroes {
-                prefered = '1'
-            } else if ones < zeroes {
-                prefered = '0'
-            } else {
-                prefered = '1'
-            }
-            if i == n - 1 {
-                prefered = '1'
-            }
+/// Oxygen (most common bit) or CO2 (least common bit) rating.
+/// Values sharing the prefix seen so far form a contiguous range of the sorted
+/// report, split by the current bit into zeroes then ones.
+fn rating(report: &Report, prefer_common: bool) -> BigUint {
+    let (mut lo, mut hi) = (0, report.values.len());
+    for pos in (0..report.width).rev() {
+        if hi - lo <= 1 {
+            break;
+        }
+        let split = lo + report.values[lo..hi].partition_point(|v| !v.bit(pos));
+        let (zeroes, ones) = (split - lo, hi - split);
+        let keep_ones = if prefer_common {
+            ones >= zeroes
         } else {
-            if ones > zeroes {
-                prefered = '0'
-            } else if ones < zeroes {
-                prefered = '1'
-            } else {
-                prefered = '0'
-            }
-            if i == n - 1 {
-                prefered = '0'
-            }
+            ones < zeroes
+        };
+        // never filter out every remaining value
+        if (keep_ones && ones > 0) || zeroes == 0 {
+            lo = split;
+        } else {
+            hi = split;
         }
-
-        data = data
-            .iter()
-            .filter(|datum| datum.chars().nth(i).unwrap() == prefered)
-            .map(|x| x.to_string())
-            .collect();
         println!(
-            "at iter {} 1={} 0={} remaining {}",
-            i,
+            "at bit {} 1={} 0={} remaining {}",
+            pos,
             ones,
             zeroes,
-            data.len()
+            hi - lo
         );
-        for x in &data {
-            println!(" {}", x);
-        }
-        if data.len() == 1 {
-            let sensor = &data[0];
-            let num = isize::from_str_radix(sensor, 2).unwrap();
-            println!("found {} {}", sensor, num);
-            return Ok(num as u32);
+    }
+    report.values[lo].clone()
+}
+
+fn to_answer(a: &BigUint, b: &BigUint) -> anyhow::Result<u64> {
+    let product = a * b;
+    match product.to_u64() {
+        Some(product) => {
+            println!("product {}", product);
+            Ok(product)
         }
+        None => bail!("product {} does not fit u64", product),
     }
-    Err("No data")
+}
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    let report = read_report(&lines);
+    print_bit_stats(&report);
+    let (gamma, epsilon) = power_rates(&report);
+    println!(
+        "\ngamma: {:0w$b}, {}, epsilon: {:0w$b}, {}",
+        gamma,
+        gamma,
+        epsilon,
+        epsilon,
+        w = report.width
+    );
+    to_answer(&gamma, &epsilon).unwrap_or_else(|e| panic!("{}", e))
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let oxygen_value = detect_sensor(lines.clone(), true).unwrap();
-    let co2_value = detect_sensor(lines, false).unwrap();
-    (oxygen_value * co2_value) as u64
+    let report = read_report(&lines);
+    let oxygen_value = rating(&report, true);
+    let co2_value = rating(&report, false);
+    println!("oxygen {}, co2 {}", oxygen_value, co2_value);
+    to_answer(&oxygen_value, &co2_value).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_lines(sample: &str) -> Vec<String> {
+        sample.lines().map(String::from).collect()
+    }
+
+    static SAMPLE: &str = "00100
+11110
+10110
+10111
+10101
+01111
+00111
+11100
+10000
+11001
+00010
+01010";
+
+    fn small(value: BigUint) -> u64 {
+        value.to_u64().expect("value does not fit u64")
+    }
+
+    #[test]
+    fn test_sample() {
+        let report = read_report(&to_lines(SAMPLE));
+        let (gamma, epsilon) = power_rates(&report);
+        assert_eq!((small(gamma), small(epsilon)), (22, 9));
+        assert_eq!(small(rating(&report, true)), 23);
+        assert_eq!(small(rating(&report, false)), 10);
+    }
+
+    #[test]
+    fn test_wide_report() {
+        // each sample line followed by 35 copies of its last bit
+        let wide: Vec<String> = to_lines(SAMPLE)
+            .iter()
+            .map(|l| l.clone() + &l[4..].repeat(35))
+            .collect();
+        let report = read_report(&wide);
+        assert_eq!(report.width, 40);
+        let extend = |v: u64| (v << 35) | if v & 1 == 1 { (1 << 35) - 1 } else { 0 };
+        let (gamma, epsilon) = power_rates(&report);
+        assert_eq!((small(gamma), small(epsilon)), (extend(22), extend(9)));
+        assert_eq!(small(rating(&report, true)), extend(23));
+        assert_eq!(small(rating(&report, false)), extend(10));
+    }
+
+    #[test]
+    fn test_very_wide_report() {
+        // sample lines padded with 95 zero bits in front
+        let wide: Vec<String> = to_lines(SAMPLE)
+            .iter()
+            .map(|l| "0".repeat(95) + l)
+            .collect();
+        let report = read_report(&wide);
+        assert_eq!(report.width, 100);
+        assert_eq!(small(rating(&report, true)), 23);
+        assert_eq!(small(rating(&report, false)), 10);
+        let (gamma, epsilon) = power_rates(&report);
+        assert_eq!(format!("{:b}", gamma), "10110");
+        assert_eq!(format!("{:b}", epsilon), "1".repeat(95) + "01001");
+        let error = to_answer(&gamma, &epsilon).unwrap_err().to_string();
+        let expected = 22 * (((1u128 << 100) - 1) ^ 22);
+        assert_eq!(error, format!("product {} does not fit u64", expected));
+    }
+
+    #[test]
+    fn test_beyond_u128_report() {
+        // sample lines behind a common leading one and 200 zero bits
+        let prefix = "1".to_string() + &"0".repeat(200);
+        let wide: Vec<String> = to_lines(SAMPLE)
+            .iter()
+            .map(|l| prefix.clone() + l)
+            .collect();
+        let report = read_report(&wide);
+        assert_eq!(report.width, 206);
+        assert_eq!(
+            format!("{:b}", rating(&report, true)),
+            prefix.clone() + "10111"
+        );
+        assert_eq!(
+            format!("{:b}", rating(&report, false)),
+            prefix.clone() + "01010"
+        );
+        let (gamma, epsilon) = power_rates(&report);
+        assert_eq!(format!("{:b}", gamma), prefix + "10110");
+        assert_eq!(format!("{:b}", epsilon), "1".repeat(200) + "01001");
+        let error = to_answer(&gamma, &epsilon).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!("product {} does not fit u64", &gamma * &epsilon)
+        );
+    }
+
+    #[test]
+    fn test_product_overflow() {
+        let big = |n: u64| BigUint::from_u64(n);
+        assert_eq!(
+            to_answer(&big(1 << 32), &big((1 << 32) - 1)).unwrap(),
+            u64::MAX - (1 << 32) + 1
+        );
+        assert!(to_answer(&big(1 << 32), &big(1 << 32)).is_err());
+        let max = BigUint::from_binary(&"1".repeat(128)).unwrap();
+        assert_eq!(
+            to_answer(&max, &max).unwrap_err().to_string(),
+            "product 115792089237316195423570985008687907852589419931798687112530834793049593217025 does not fit u64"
+        );
+    }
 }
@@ -1,12 +1,13 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fmt;
 
-use crate::{answer, common::Answer};
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(4512, 1924);
 
 struct Board {
-    size: usize,
+    rows: usize,
+    cols: usize,
     data: Vec<Vec<i32>>,
     marked: Vec<Vec<bool>>,
 }
@@ -17,10 +18,10 @@ struct Board {
 
 impl fmt::Debug for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Board: {{size={}, data=", self.size)?;
-        for r in 0..self.size {
+        writeln!(f, "Board: {{size={}x{}, data=", self.rows, self.cols)?;
+        for r in 0..self.rows {
             write!(f, "   ")?;
-            for c in 0..self.size {
+            for c in 0..self.cols {
                 let mark = if self.marked[r][c] { "*" } else { " " };
                 write!(f, "{:>2}{}  ", self.data[r][c], mark)?;
             }
@@ -35,28 +36,151 @@ impl fmt::Debug for Board {
     }
 }
 
+/// Way to complete a board, any of chosen rules wins
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    Rows,
+    Columns,
+    // both corner-to-corner diagonals of the largest square from top corners
+    Diagonals,
+    Corners,
+    FullCard,
+    // cells marked as true must be all drawn
+    Pattern(Vec<Vec<bool>>),
+}
+
+fn parse_rule(s: &str) -> Rule {
+    match s {
+        "rows" => Rule::Rows,
+        "columns" => Rule::Columns,
+        "diagonals" => Rule::Diagonals,
+        "corners" => Rule::Corners,
+        "full" => Rule::FullCard,
+        _ => panic!("Unknown rule {}", s),
+    }
+}
+
+/// Pattern mask like `10001/01010/00100` with rows separated by slashes
+fn parse_pattern(s: &str) -> Rule {
+    let mask: Vec<Vec<bool>> = s
+        .split('/')
+        .map(|row| {
+            row.chars()
+                .map(|c| match c {
+                    '1' | 'x' => true,
+                    '0' | '.' => false,
+                    _ => panic!("Wrong pattern char {}", c),
+                })
+                .collect()
+        })
+        .collect();
+    assert!(
+        mask.iter().flatten().any(|cell| *cell),
+        "Pattern {} has no cells",
+        s
+    );
+    Rule::Pattern(mask)
+}
+
+/// Rules from `--rules=rows,columns,...` and `--pattern=...` options, rows and columns by default
+fn read_rules() -> Vec<Rule> {
+    let mut rules: Vec<Rule> = match common::option("rules") {
+        Some(names) => names
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(parse_rule)
+            .collect(),
+        None => vec![Rule::Rows, Rule::Columns],
+    };
+    if let Some(pattern) = common::option("pattern") {
+        rules.push(parse_pattern(pattern));
+    }
+    assert!(!rules.is_empty(), "No rules");
+    rules
+}
+
+type Cell = (usize, usize);
+
+/// Sets of cells for a board of given shape; a board wins when any set is fully marked
+fn winning_lines(rules: &[Rule], rows: usize, cols: usize) -> Vec<Vec<Cell>> {
+    let mut lines: Vec<Vec<Cell>> = Vec::new();
+    for rule in rules {
+        match rule {
+            Rule::Rows => {
+                for r in 0..rows {
+                    lines.push((0..cols).map(|c| (r, c)).collect());
+                }
+            }
+            Rule::Columns => {
+                for c in 0..cols {
+                    lines.push((0..rows).map(|r| (r, c)).collect());
+                }
+            }
+            Rule::Diagonals => {
+                let size = rows.min(cols);
+                lines.push((0..size).map(|i| (i, i)).collect());
+                lines.push((0..size).map(|i| (i, cols - 1 - i)).collect());
+            }
+            Rule::Corners => {
+                lines.push(vec![
+                    (0, 0),
+                    (0, cols - 1),
+                    (rows - 1, 0),
+                    (rows - 1, cols - 1),
+                ]);
+            }
+            Rule::FullCard => {
+                lines.push(
+                    (0..rows)
+                        .flat_map(|r| (0..cols).map(move |c| (r, c)))
+                        .collect(),
+                );
+            }
+            Rule::Pattern(mask) => {
+                assert!(
+                    mask.len() == rows && mask.iter().all(|row| row.len() == cols),
+                    "Pattern does not match board {}x{}",
+                    rows,
+                    cols
+                );
+                lines.push(
+                    (0..rows)
+                        .flat_map(|r| (0..cols).map(move |c| (r, c)))
+                        .filter(|(r, c)| mask[*r][*c])
+                        .collect(),
+                );
+            }
+        }
+    }
+    lines
+}
+
 fn read_numbers(lines: &[String]) -> Vec<i32> {
     let line = lines.first().unwrap();
     let numbers: Vec<i32> = line.trim().split(',').map(|s| s.parse().unwrap()).collect();
     numbers
 }
 
-fn read_board(lines: &Vec<String>) -> Board {
-    let mut board = Board {
-        size: lines.len() as usize,
-        data: Vec::new(),
-        marked: Vec::new(),
-    };
-    for line in lines {
-        let row = line
-            .split_whitespace()
-            .map(|s| s.trim().parse().unwrap())
-            .collect();
-        board.data.push(row);
-        let marked = vec![false; board.size];
-        board.marked.push(marked);
+fn read_board(lines: &[String]) -> Board {
+    let data: Vec<Vec<i32>> = lines
+        .iter()
+        .map(|line| {
+            line.split_whitespace()
+                .map(|s| s.trim().parse().unwrap())
+                .collect()
+        })
+        .collect();
+    let rows = data.len();
+    let cols = data[0].len();
+    if cols == 0 || data.iter().any(|row| row.len() != cols) {
+        panic!("Wrong board shape");
+    }
+    Board {
+        rows,
+        cols,
+        data,
+        marked: vec![vec![false; cols]; rows],
     }
-    board
 }
 
 fn read_boards(in_lines: &[String]) -> Vec<Board> {
@@ -79,138 +203,343 @@ fn read_boards(in_lines: &[String]) -> Vec<Board> {
     boards
 }
 
-fn draw_number_a(number: i32, boards: &mut Vec<Board>) -> Option<&mut Board> {
-    for board in boards {
-        // mark number
-        for col in 0..board.size {
-            for row in 0..board.size {
-                if board.data[col][row] == number {
-                    board.marked[col][row] = true;
-                }
-            }
-        }
-        if does_win(board) {
-            return Some(board);
-        }
-    }
-    None
+#[derive(Debug, PartialEq)]
+struct Win {
+    board: usize,
+    // index of the drawn number completing the board
+    turn: usize,
+    number: i32,
+    score: u64,
 }
 
-fn does_win(board: &Board) -> bool {
-    // determine is it a winner by rows
-    for col in 0..board.size {
-        let mut all = true;
-        for row in 0..board.size {
-            if !board.marked[col][row] {
-                all = false;
+/// Winning lines of one board shape with lines passing through each cell
+struct ShapeLines {
+    lengths: Vec<usize>,
+    cell_lines: Vec<Vec<Vec<usize>>>,
+}
+
+impl ShapeLines {
+    fn new(rules: &[Rule], rows: usize, cols: usize) -> ShapeLines {
+        let lines = winning_lines(rules, rows, cols);
+        let mut cell_lines: Vec<Vec<Vec<usize>>> = vec![vec![Vec::new(); cols]; rows];
+        for (idx, line) in lines.iter().enumerate() {
+            for (row, col) in line {
+                cell_lines[*row][*col].push(idx);
             }
         }
-        if all {
-            return true;
+        ShapeLines {
+            lengths: lines.iter().map(|line| line.len()).collect(),
+            cell_lines,
         }
     }
-    // determine is it a winner by cols
-    for row in 0..board.size {
-        let mut all = true;
-        for col in 0..board.size {
-            if !board.marked[col][row] {
-                all = false;
+}
+
+/// Boards in the order they win, boards winning on the same number are ordered by index.
+/// Each drawn number touches only cells where it occurs and counts hits per line.
+fn rank_boards(numbers: &[i32], boards: &mut [Board], rules: &[Rule]) -> Vec<Win> {
+    // number to (board, row, col) of every occurrence, ordered by board
+    let mut index: HashMap<i32, Vec<(usize, usize, usize)>> = HashMap::new();
+    let mut shapes: HashMap<(usize, usize), ShapeLines> = HashMap::new();
+    for (idx, board) in boards.iter().enumerate() {
+        for row in 0..board.rows {
+            for col in 0..board.cols {
+                index
+                    .entry(board.data[row][col])
+                    .or_default()
+                    .push((idx, row, col));
             }
         }
-        if all {
-            return true;
-        }
+        shapes
+            .entry((board.rows, board.cols))
+            .or_insert_with(|| ShapeLines::new(rules, board.rows, board.cols));
     }
-    false
-}
+    let mut hits: Vec<Vec<usize>> = boards
+        .iter()
+        .map(|board| vec![0; shapes[&(board.rows, board.cols)].lengths.len()])
+        .collect();
+    let mut unmarked_sums: Vec<i64> = boards
+        .iter()
+        .map(|board| board.data.iter().flatten().map(|v| *v as i64).sum())
+        .collect();
+    let mut won: Vec<bool> = vec![false; boards.len()];
 
-fn draw_number_b(number: i32, boards: &mut [Board]) -> Option<&mut Board> {
-    let winned: Vec<bool> = boards.iter().map(does_win).collect();
-    for (ind, board) in &mut boards.iter_mut().enumerate() {
-        // mark number
-        for col in 0..board.size {
-            for row in 0..board.size {
-                if board.data[col][row] == number {
-                    board.marked[col][row] = true;
+    let mut wins: Vec<Win> = Vec::new();
+    for (turn, number) in numbers.iter().enumerate() {
+        let occurrences = index.get(number).map_or(&[][..], |o| o.as_slice());
+        // mark every occurrence on a board before checking whether it won
+        for board_occurrences in occurrences.chunk_by(|a, b| a.0 == b.0) {
+            let idx = board_occurrences[0].0;
+            if won[idx] {
+                continue;
+            }
+            let board = &mut boards[idx];
+            let shape = &shapes[&(board.rows, board.cols)];
+            for (_, row, col) in board_occurrences {
+                if board.marked[*row][*col] {
+                    continue;
+                }
+                board.marked[*row][*col] = true;
+                unmarked_sums[idx] -= *number as i64;
+                for line in &shape.cell_lines[*row][*col] {
+                    hits[idx][*line] += 1;
+                    if hits[idx][*line] == shape.lengths[*line] {
+                        won[idx] = true;
+                    }
                 }
             }
-        }
-        // if all other wins
-        let mut all_other_win = !winned[ind];
-        for (win_ind, win_item) in winned.iter().enumerate() {
-            if ind != win_ind && !win_item {
-                all_other_win = false;
+            if won[idx] {
+                wins.push(Win {
+                    board: idx,
+                    turn,
+                    number: *number,
+                    score: (*number as i64 * unmarked_sums[idx]) as u64,
+                });
             }
         }
-        println!(
-            "For num {} winned {:?} ind {} all other win={}",
-            number, winned, ind, all_other_win
-        );
-        if all_other_win && does_win(board) {
-            return Some(board);
+        if wins.len() == boards.len() {
+            break;
         }
     }
-    None
+    wins
 }
 
-fn determine_score(number: i32, board: &Board) -> u64 {
-    let mut sum = 0;
-    for col in 0..board.size {
-        for row in 0..board.size {
-            if !board.marked[col][row] {
-                sum += board.data[col][row];
-            }
-        }
+fn print_ranking(wins: &[Win], boards: &[Board]) {
+    for (place, win) in wins.iter().enumerate() {
+        println!(
+            "#{} board {} wins at turn {} on number {} with score {}",
+            place + 1,
+            win.board,
+            win.turn + 1,
+            win.number,
+            win.score
+        );
+    }
+    if wins.len() < boards.len() {
+        println!("{} boards never win", boards.len() - wins.len());
     }
-    (number * sum) as u64
 }
 
-fn find_winning_board(numbers: Vec<i32>, boards: &mut Vec<Board>) -> u64 {
-    for number in numbers {
-        if let Some(winning_board) = draw_number_a(number, boards) {
-            println!("Winning number {}, board {:?}", number, winning_board);
-            return determine_score(number, winning_board);
-        }
-    }
-    panic!("No result")
+fn find_winning_board(numbers: Vec<i32>, boards: &mut [Board], rules: &[Rule]) -> u64 {
+    let wins = rank_boards(&numbers, boards, rules);
+    print_ranking(&wins, boards);
+    let winning = wins.first().expect("No result");
+    println!(
+        "Winning number {}, board {:?}",
+        winning.number, boards[winning.board]
+    );
+    winning.score
 }
 
-fn find_losing_board(numbers: Vec<i32>, boards: &mut Vec<Board>) -> u64 {
-    for number in numbers {
-        if let Some(winning_board) = draw_number_b(number, boards) {
-            println!("Winning number {}, board {:?}", number, winning_board);
-            return determine_score(number, winning_board);
-        }
-    }
-    panic!("No result")
+fn find_losing_board(numbers: Vec<i32>, boards: &mut [Board], rules: &[Rule]) -> u64 {
+    let wins = rank_boards(&numbers, boards, rules);
+    print_ranking(&wins, boards);
+    let losing = wins.last().expect("No result");
+    println!(
+        "Winning number {}, board {:?}",
+        losing.number, boards[losing.board]
+    );
+    losing.score
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
-    let numbers = read_numbers(&lines);
+fn read_problem(lines: &[String]) -> (Vec<i32>, Vec<Board>) {
+    let numbers = read_numbers(lines);
     println!("Numbers: {:?}", numbers);
-    let mut boards = read_boards(&lines);
-    let sizes: HashSet<usize> = HashSet::from_iter(boards.iter().map(|b| b.size));
-    println!("Board count {} of sizes {:?}", boards.len(), sizes);
-    if sizes.len() != 1 || *sizes.iter().next().unwrap() == 0 {
-        panic!("Wrong sizes");
-    }
+    let boards = read_boards(lines);
+    let mut shapes: Vec<(usize, usize)> = boards.iter().map(|b| (b.rows, b.cols)).collect();
+    shapes.sort();
+    shapes.dedup();
+    println!("Board count {} of shapes {:?}", boards.len(), shapes);
     for b in &boards {
         println!("{:?}", b);
     }
-    find_winning_board(numbers, &mut boards)
+    (numbers, boards)
+}
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    let (numbers, mut boards) = read_problem(&lines);
+    find_winning_board(numbers, &mut boards, &read_rules())
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let numbers = read_numbers(&lines);
-    println!("Numbers: {:?}", numbers);
-    let mut boards = read_boards(&lines);
-    let sizes: HashSet<usize> = HashSet::from_iter(boards.iter().map(|b| b.size));
-    println!("Board count {} of sizes {:?}", boards.len(), sizes);
-    if sizes.len() != 1 || *sizes.iter().next().unwrap() == 0 {
-        panic!("Wrong sizes");
+    let (numbers, mut boards) = read_problem(&lines);
+    find_losing_board(numbers, &mut boards, &read_rules())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    fn read_sample() -> (Vec<i32>, Vec<Board>) {
+        let lines: Vec<String> = include_str!("../data/day4.dat")
+            .lines()
+            .map(String::from)
+            .collect();
+        read_problem(&lines)
     }
-    for b in &boards {
-        println!("{:?}", b);
+
+    /// Straightforward ranking rescanning every board on every draw
+    fn rank_boards_naive(numbers: &[i32], boards: &mut [Board], rules: &[Rule]) -> Vec<Win> {
+        let mut won: Vec<bool> = vec![false; boards.len()];
+        let mut wins: Vec<Win> = Vec::new();
+        for (turn, number) in numbers.iter().enumerate() {
+            for (idx, board) in boards.iter_mut().enumerate() {
+                if won[idx] {
+                    continue;
+                }
+                let mut sum = 0;
+                for row in 0..board.rows {
+                    for col in 0..board.cols {
+                        if board.data[row][col] == *number {
+                            board.marked[row][col] = true;
+                        }
+                        if !board.marked[row][col] {
+                            sum += board.data[row][col];
+                        }
+                    }
+                }
+                let lines = winning_lines(rules, board.rows, board.cols);
+                if lines
+                    .iter()
+                    .any(|line| line.iter().all(|(row, col)| board.marked[*row][*col]))
+                {
+                    won[idx] = true;
+                    wins.push(Win {
+                        board: idx,
+                        turn,
+                        number: *number,
+                        score: (number * sum) as u64,
+                    });
+                }
+            }
+        }
+        wins
+    }
+
+    /// Boards filled from `values` numbers, repeated within a board when `repeat` is set
+    fn random_boards(
+        rng: &mut StdRng,
+        count: usize,
+        rows: usize,
+        cols: usize,
+        values: i32,
+        repeat: bool,
+    ) -> Vec<Board> {
+        (0..count)
+            .map(|_| {
+                let values: Vec<i32> = if repeat {
+                    (0..rows * cols).map(|_| rng.gen_range(0..values)).collect()
+                } else {
+                    let mut values: Vec<i32> = (0..values).collect();
+                    values.shuffle(rng);
+                    values
+                };
+                let data: Vec<Vec<i32>> = values[..rows * cols]
+                    .chunks(cols)
+                    .map(|row| row.to_vec())
+                    .collect();
+                Board {
+                    rows,
+                    cols,
+                    data,
+                    marked: vec![vec![false; cols]; rows],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_random_against_naive() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut numbers: Vec<i32> = (0..100).collect();
+        numbers.shuffle(&mut rng);
+        let rules = [Rule::Rows, Rule::Columns, Rule::Diagonals];
+        let mut boards = random_boards(&mut rng, 300, 5, 5, 100, false);
+        boards.extend(random_boards(&mut rng, 100, 3, 6, 100, false));
+        boards.extend(random_boards(&mut rng, 100, 4, 4, 12, true));
+        let mut boards_naive: Vec<Board> = boards
+            .iter()
+            .map(|board| Board {
+                rows: board.rows,
+                cols: board.cols,
+                data: board.data.clone(),
+                marked: board.marked.clone(),
+            })
+            .collect();
+        let wins = rank_boards(&numbers, &mut boards, &rules);
+        assert_eq!(wins.len(), 500);
+        assert_eq!(wins, rank_boards_naive(&numbers, &mut boards_naive, &rules));
+    }
+
+    #[test]
+    fn test_rank_many_boards() {
+        let mut rng = StdRng::seed_from_u64(38);
+        let mut numbers: Vec<i32> = (0..100).collect();
+        numbers.shuffle(&mut rng);
+        let mut boards = random_boards(&mut rng, 20000, 5, 5, 100, false);
+        let wins = rank_boards(&numbers, &mut boards, &[Rule::Rows, Rule::Columns]);
+        assert_eq!(wins.len(), 20000);
+        assert!(wins.windows(2).all(|w| w[0].turn <= w[1].turn));
+    }
+
+    fn read_sample_board(sample: &str) -> Board {
+        let lines: Vec<String> = sample.lines().map(String::from).collect();
+        read_board(&lines)
+    }
+
+    #[test]
+    fn test_rank_sample() {
+        let (numbers, mut boards) = read_sample();
+        let wins = rank_boards(&numbers, &mut boards, &[Rule::Rows, Rule::Columns]);
+        assert_eq!(wins.len(), 3);
+        assert_eq!(wins.iter().map(|w| w.board).collect::<Vec<_>>(), [2, 0, 1]);
+        assert_eq!(wins.first().unwrap().score, 4512);
+        assert_eq!(wins.last().unwrap().score, 1924);
+    }
+
+    #[test]
+    fn test_rules() {
+        let sample = "1 2 3\n4 5 6\n7 8 9";
+        let rank = |numbers: &[i32], rules: &[Rule]| {
+            let mut boards = vec![read_sample_board(sample)];
+            rank_boards(numbers, &mut boards, rules)
+                .first()
+                .map(|win| win.turn)
+        };
+        assert_eq!(rank(&[1, 5, 9], &[Rule::Rows, Rule::Columns]), None);
+        assert_eq!(rank(&[1, 5, 9], &[Rule::Diagonals]), Some(2));
+        assert_eq!(rank(&[9, 3, 5, 7], &[Rule::Diagonals]), Some(3));
+        assert_eq!(rank(&[1, 3, 5, 7, 9], &[Rule::Corners]), Some(4));
+        assert_eq!(rank(&[1, 2, 3, 4, 5, 6, 7, 8], &[Rule::FullCard]), None);
+        let pattern = parse_pattern("010/111/010");
+        assert_eq!(rank(&[1, 2, 4, 5, 6, 9, 8], &[pattern]), Some(6));
+    }
+
+    #[test]
+    fn test_non_square() {
+        let mut boards = vec![read_sample_board("1 2 3 4\n5 6 7 8")];
+        assert_eq!((boards[0].rows, boards[0].cols), (2, 4));
+        let wins = rank_boards(&[4, 8, 1], &mut boards, &[Rule::Rows, Rule::Columns]);
+        assert_eq!(wins[0].turn, 1);
+        assert_eq!(wins[0].score, 8 * (1 + 2 + 3 + 5 + 6 + 7));
+        assert_eq!(
+            winning_lines(&[Rule::Diagonals], 2, 4),
+            vec![vec![(0, 0), (1, 1)], vec![(0, 3), (1, 2)]]
+        );
+    }
+
+    #[test]
+    fn test_repeated_numbers() {
+        let mut boards = vec![read_sample_board("1 2\n3 1")];
+        let wins = rank_boards(&[2, 1], &mut boards, &[Rule::Rows]);
+        assert_eq!(wins[0].score, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no cells")]
+    fn test_empty_pattern() {
+        parse_pattern("000/000");
     }
-    find_losing_board(numbers, &mut boards)
 }
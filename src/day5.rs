@@ -1,12 +1,17 @@
+use std::collections::HashMap;
 use std::fmt;
 
-use crate::{answer, common::Answer};
+use anyhow::bail;
+
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(5, 12);
 
+type Point = (i64, i64);
+
 struct Line {
-    from: (u32, u32),
-    to: (u32, u32),
+    from: Point,
+    to: Point,
 }
 
 impl fmt::Debug for Line {
@@ -18,6 +23,16 @@ impl fmt::Debug for Line {
     }
 }
 
+impl Line {
+    fn is_axis_aligned(&self) -> bool {
+        self.from.0 == self.to.0 || self.from.1 == self.to.1
+    }
+
+    fn is_diagonal(&self) -> bool {
+        (self.to.0 - self.from.0).abs() == (self.to.1 - self.from.1).abs()
+    }
+}
+
 fn read_line(line: &str) -> Line {
     let comp: Vec<&str> = line.split(" -> ").collect();
     if comp.len() != 2 {
@@ -27,39 +42,53 @@ fn read_line(line: &str) -> Line {
     let second_comp: Vec<&str> = comp[1].split(',').collect();
     Line {
         from: (
-            first_comp[0].parse().unwrap(),
-            first_comp[1].parse().unwrap(),
+            first_comp[0].trim().parse().unwrap(),
+            first_comp[1].trim().parse().unwrap(),
         ),
         to: (
-            second_comp[0].parse().unwrap(),
-            second_comp[1].parse().unwrap(),
+            second_comp[0].trim().parse().unwrap(),
+            second_comp[1].trim().parse().unwrap(),
         ),
     }
 }
 
+/// Sparse coverage map storing only covered points
 struct Board {
-    // top-level are cols so it can be indexed as [x][y]
-    points: Vec<Vec<u32>>,
+    points: HashMap<Point, u32>,
 }
 
+// do not print maps larger than this
+const MAX_PRINT_SIZE: i64 = 100;
+
 impl Board {
     fn print(&self) {
-        let mut max_val: u32 = 0;
-        for col in &self.points {
-            for v in col {
-                max_val = std::cmp::max(max_val, *v);
-            }
+        if self.points.is_empty() {
+            return;
         }
+        let min_x = self.points.keys().map(|p| p.0).min().unwrap();
+        let max_x = self.points.keys().map(|p| p.0).max().unwrap();
+        let min_y = self.points.keys().map(|p| p.1).min().unwrap();
+        let max_y = self.points.keys().map(|p| p.1).max().unwrap();
+        if max_x - min_x >= MAX_PRINT_SIZE || max_y - min_y >= MAX_PRINT_SIZE {
+            println!(
+                "Map of {} points in ({},{})..({},{})",
+                self.points.len(),
+                min_x,
+                min_y,
+                max_x,
+                max_y
+            );
+            return;
+        }
+        let max_val: u32 = *self.points.values().max().unwrap();
         let width: usize = (max_val as f32).log10().round() as usize;
 
-        let height = self.points[0].len();
-        for y in 0..height {
+        for y in min_y..=max_y {
             print!("   ");
-            for x in 0..self.points.len() {
-                if self.points[x][y] == 0 {
-                    print!(".");
-                } else {
-                    print!("{:>width$}", self.points[x][y], width = width);
+            for x in min_x..=max_x {
+                match self.points.get(&(x, y)) {
+                    Some(v) => print!("{:>width$}", v, width = width),
+                    None => print!("."),
                 }
             }
             println!();
@@ -68,71 +97,52 @@ impl Board {
 }
 
 fn read_lines(lines: Vec<String>) -> Vec<Line> {
-    lines.iter().map(|s| read_line(s)).collect()
+    lines
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| read_line(s))
+        .collect()
+}
+
+/// Points of a line with any integer slope by Bresenham's algorithm
+fn rasterize(line: &Line) -> Vec<Point> {
+    let (mut x, mut y) = line.from;
+    let (x2, y2) = line.to;
+    let dx = (x2 - x).abs();
+    let dy = -(y2 - y).abs();
+    let xstep: i64 = if x < x2 { 1 } else { -1 };
+    let ystep: i64 = if y < y2 { 1 } else { -1 };
+    let mut err = dx + dy;
+    let mut points: Vec<Point> = Vec::with_capacity((dx - dy + 1) as usize);
+    loop {
+        points.push((x, y));
+        if x == x2 && y == y2 {
+            break;
+        }
+        let err2 = 2 * err;
+        if err2 >= dy {
+            err += dy;
+            x += xstep;
+        }
+        if err2 <= dx {
+            err += dx;
+            y += ystep;
+        }
+    }
+    points
 }
 
 fn make_map(lines: Vec<Line>, allow_diag: bool) -> Board {
-    let mut max_x = 0;
-    let mut max_y = 0;
-    // line is (x,y)
-    for line in &lines {
-        max_x = std::cmp::max(max_x, line.from.0);
-        max_x = std::cmp::max(max_x, line.to.0);
-        max_y = std::cmp::max(max_y, line.from.1);
-        max_y = std::cmp::max(max_y, line.to.1);
-    }
-    max_x += 1;
-    max_y += 1;
     let mut board = Board {
-        points: vec![vec![0; max_y as usize]; max_x as usize],
+        points: HashMap::new(),
     };
 
     for line in lines {
-        let from_x = if line.from.0 < line.to.0 {
-            line.from.0
-        } else {
-            line.to.0
-        };
-        let to_x = if line.from.0 < line.to.0 {
-            line.to.0
-        } else {
-            line.from.0
-        };
-        let from_y = if line.from.1 < line.to.1 {
-            line.from.1
-        } else {
-            line.to.1
-        };
-        let to_y = if line.from.1 < line.to.1 {
-            line.to.1
-        } else {
-            line.from.1
-        };
-        assert!(from_x <= to_x);
-        assert!(from_y <= to_y);
-        if line.from.1 == line.to.1 {
-            // x1, y1 -> x2, y1
-            let y = line.from.1;
-            for x in from_x..(to_x + 1) {
-                board.points[x as usize][y as usize] += 1;
-            }
-        } else if line.from.0 == line.to.0 {
-            // x1, y1 -> x1, y2
-            let x = line.from.0;
-            for y in from_y..(to_y + 1) {
-                board.points[x as usize][y as usize] += 1;
-            }
-        } else if allow_diag {
-            for ind in 0..(to_x - from_x + 1) {
-                //println!("{}", ind;
-                let xstep: i32 = if line.from.0 < line.to.0 { 1 } else { -1 };
-                let ystep: i32 = if line.from.1 < line.to.1 { 1 } else { -1 };
-                //println!("{} {}", xstep, ystep);
-                let x = line.from.0 as i32 + xstep * (ind as i32);
-                let y = line.from.1 as i32 + ystep * (ind as i32);
-                //println!("{} {}", x, y);
-                board.points[x as usize][y as usize] += 1;
-            }
+        if !allow_diag && !line.is_axis_aligned() {
+            continue;
+        }
+        for point in rasterize(&line) {
+            *board.points.entry(point).or_default() += 1;
         }
         //println!("\nLine {:?}", line);
         //board.print();
@@ -141,25 +151,308 @@ fn make_map(lines: Vec<Line>, allow_diag: bool) -> Board {
 }
 
 fn determine_score(board: Board) -> u64 {
-    let mut count = 0;
-    for col in board.points {
-        for v in col {
-            if v >= 2 {
-                count += 1;
+    board.points.values().filter(|v| **v >= 2).count() as u64
+}
+
+/* Analytic counting */
+
+/// Canonical line: reduced direction and offset along the normal
+type LineKey = (i64, i64, i128);
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn cross(a: (i128, i128), b: (i128, i128)) -> i128 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn delta(line: &Line) -> (i128, i128) {
+    (
+        (line.to.0 - line.from.0) as i128,
+        (line.to.1 - line.from.1) as i128,
+    )
+}
+
+fn line_key(line: &Line) -> LineKey {
+    let (dx, dy) = (line.to.0 - line.from.0, line.to.1 - line.from.1);
+    let (dx, dy) = match gcd(dx, dy) {
+        0 => (1, 0),
+        g => (dx / g, dy / g),
+    };
+    let (dx, dy) = if dx < 0 || (dx == 0 && dy < 0) {
+        (-dx, -dy)
+    } else {
+        (dx, dy)
+    };
+    let offset = dy as i128 * line.from.0 as i128 - dx as i128 * line.from.1 as i128;
+    (dx, dy, offset)
+}
+
+/// Position of a point along its line; consecutive lattice points differ by `line_step`
+fn line_param(key: &LineKey, p: Point) -> i128 {
+    key.0 as i128 * p.0 as i128 + key.1 as i128 * p.1 as i128
+}
+
+fn line_step(key: &LineKey) -> i128 {
+    key.0 as i128 * key.0 as i128 + key.1 as i128 * key.1 as i128
+}
+
+/// Closed parameter ranges covered at least twice by collinear segments
+fn collinear_overlaps(ranges: &[(i128, i128)], step: i128) -> Vec<(i128, i128)> {
+    let mut events: Vec<(i128, i32)> = ranges
+        .iter()
+        .flat_map(|(lo, hi)| [(*lo, 1), (*hi + step, -1)])
+        .collect();
+    events.sort();
+
+    let mut overlaps = vec![];
+    let mut cover = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < events.len() {
+        let pos = events[i].0;
+        let before = cover;
+        while i < events.len() && events[i].0 == pos {
+            cover += events[i].1;
+            i += 1;
+        }
+        if before < 2 && cover >= 2 {
+            start = pos;
+        } else if before >= 2 && cover < 2 {
+            overlaps.push((start, pos - step));
+        }
+    }
+    overlaps
+}
+
+/// Lattice point shared by two non-collinear segments, if any
+fn crossing(a: &Line, b: &Line) -> Option<Point> {
+    let (r, s) = (delta(a), delta(b));
+    let qp = ((b.from.0 - a.from.0) as i128, (b.from.1 - a.from.1) as i128);
+    let on_segment = |p: Point, line: &Line| {
+        let d = delta(line);
+        let pq = ((p.0 - line.from.0) as i128, (p.1 - line.from.1) as i128);
+        cross(d, pq) == 0
+            && line.from.0.min(line.to.0) <= p.0
+            && p.0 <= line.from.0.max(line.to.0)
+            && line.from.1.min(line.to.1) <= p.1
+            && p.1 <= line.from.1.max(line.to.1)
+    };
+    if r == (0, 0) {
+        return on_segment(a.from, b).then_some(a.from);
+    }
+    if s == (0, 0) {
+        return on_segment(b.from, a).then_some(b.from);
+    }
+
+    let mut denom = cross(r, s);
+    if denom == 0 {
+        return None;
+    }
+    let mut t = cross(qp, s);
+    let mut u = cross(qp, r);
+    if denom < 0 {
+        denom = -denom;
+        t = -t;
+        u = -u;
+    }
+    if t < 0 || t > denom || u < 0 || u > denom {
+        return None;
+    }
+    if (r.0 * t) % denom != 0 || (r.1 * t) % denom != 0 {
+        return None;
+    }
+    Some((
+        a.from.0 + (r.0 * t / denom) as i64,
+        a.from.1 + (r.1 * t / denom) as i64,
+    ))
+}
+
+/// Count lattice points covered by at least two lines without rasterizing them.
+/// Only horizontal, vertical and 45 degree lines are supported, their lattice
+/// points are exactly the points drawn by `rasterize`.
+fn count_overlaps(lines: &[Line], allow_diag: bool) -> anyhow::Result<u64> {
+    let lines: Vec<&Line> = lines
+        .iter()
+        .filter(|l| allow_diag || l.is_axis_aligned())
+        .collect();
+    if let Some(line) = lines
+        .iter()
+        .find(|l| !l.is_axis_aligned() && !l.is_diagonal())
+    {
+        bail!(
+            "{:?} is neither axis-aligned nor diagonal, count it without --analytic",
+            line
+        );
+    }
+    let keys: Vec<LineKey> = lines.iter().map(|l| line_key(l)).collect();
+
+    let mut groups: HashMap<LineKey, Vec<(i128, i128)>> = HashMap::new();
+    for (line, key) in lines.iter().zip(&keys) {
+        let (a, b) = (line_param(key, line.from), line_param(key, line.to));
+        groups.entry(*key).or_default().push((a.min(b), a.max(b)));
+    }
+    let overlaps: HashMap<LineKey, Vec<(i128, i128)>> = groups
+        .iter()
+        .map(|(key, ranges)| (*key, collinear_overlaps(ranges, line_step(key))))
+        .filter(|(_, o)| !o.is_empty())
+        .collect();
+    let mut count: u64 = overlaps
+        .iter()
+        .flat_map(|(key, o)| o.iter().map(move |(lo, hi)| (hi - lo) / line_step(key) + 1))
+        .sum::<i128>() as u64;
+
+    let mut crossings: HashMap<Point, Vec<LineKey>> = HashMap::new();
+    for i in 0..lines.len() {
+        for j in i + 1..lines.len() {
+            if keys[i] == keys[j] {
+                continue;
+            }
+            if let Some(p) = crossing(lines[i], lines[j]) {
+                let entry = crossings.entry(p).or_default();
+                entry.push(keys[i]);
+                entry.push(keys[j]);
             }
         }
     }
-    count
+    // crossings inside a collinear overlap are already counted
+    for (p, keys) in &crossings {
+        let counted = keys.iter().any(|key| {
+            let u = line_param(key, *p);
+            overlaps
+                .get(key)
+                .is_some_and(|o| o.iter().any(|(lo, hi)| *lo <= u && u <= *hi))
+        });
+        if !counted {
+            count += 1;
+        }
+    }
+    println!(
+        "{} collinear overlaps, {} crossings",
+        overlaps.values().map(|o| o.len()).sum::<usize>(),
+        crossings.len()
+    );
+    Ok(count)
 }
 
 pub fn process_a(lines: Vec<String>) -> u64 {
-    let board = make_map(read_lines(lines), false);
+    let lines = read_lines(lines);
+    if common::option("analytic").is_some() {
+        return count_overlaps(&lines, false).unwrap_or_else(|e| panic!("{}", e));
+    }
+    let board = make_map(lines, false);
     board.print();
     determine_score(board)
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let board = make_map(read_lines(lines), true);
+    let lines = read_lines(lines);
+    if common::option("analytic").is_some() {
+        return count_overlaps(&lines, true).unwrap_or_else(|e| panic!("{}", e));
+    }
+    let board = make_map(lines, true);
     board.print();
     determine_score(board)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_sample(sample: &str) -> Vec<Line> {
+        read_lines(sample.lines().map(String::from).collect())
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let line = &read_sample("1,1 -> 1,3")[0];
+        assert_eq!(rasterize(line), vec![(1, 1), (1, 2), (1, 3)]);
+        let line = &read_sample("9,7 -> 7,9")[0];
+        assert_eq!(rasterize(line), vec![(9, 7), (8, 8), (7, 9)]);
+        let line = &read_sample("0,0 -> 4,2")[0];
+        assert_eq!(
+            rasterize(line),
+            vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]
+        );
+        let line = &read_sample("-2,-3 -> -2,-3")[0];
+        assert_eq!(rasterize(line), vec![(-2, -3)]);
+    }
+
+    #[test]
+    fn test_sparse_map() {
+        let lines = read_sample("-5,-5 -> 5,5\n5,-5 -> -5,5\n0,-100000 -> 0,1000000");
+        let board = make_map(lines, true);
+        assert_eq!(determine_score(board), 1);
+
+        let lines = read_sample("0,0 -> 6,3\n0,3 -> 6,0\n2,1 -> 2,5");
+        let board = make_map(lines, false);
+        assert_eq!(determine_score(board), 0);
+    }
+
+    static SAMPLE: &str = "0,9 -> 5,9
+8,0 -> 0,8
+9,4 -> 3,4
+2,2 -> 2,1
+7,0 -> 7,4
+6,4 -> 2,0
+0,9 -> 2,9
+3,4 -> 1,4
+0,0 -> 8,8
+5,5 -> 8,2";
+
+    #[test]
+    fn test_count_overlaps_sample() {
+        for allow_diag in [false, true] {
+            let expected = determine_score(make_map(read_sample(SAMPLE), allow_diag));
+            assert_eq!(
+                count_overlaps(&read_sample(SAMPLE), allow_diag).unwrap(),
+                expected
+            );
+        }
+        assert_eq!(count_overlaps(&read_sample(SAMPLE), true).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_count_overlaps_random() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let directions = [(1, 0), (0, 1), (1, 1), (1, -1), (2, 1), (1, -3), (3, 2)];
+        for round in 0..100 {
+            // every other sample may contain lines of other slopes
+            let slopes = if round % 2 == 0 { 4 } else { directions.len() };
+            let mut sample = String::new();
+            for _ in 0..30 {
+                let (x, y) = (rng.gen_range(-20..20), rng.gen_range(-20..20));
+                let len: i64 = rng.gen_range(0..8);
+                let (dx, dy) = directions[rng.gen_range(0..slopes)];
+                sample += &format!("{},{} -> {},{}\n", x, y, x + dx * len, y + dy * len);
+            }
+            let lines = read_sample(&sample);
+            let supported = lines.iter().all(|l| l.is_axis_aligned() || l.is_diagonal());
+            let expected = determine_score(make_map(read_sample(&sample), true));
+            match count_overlaps(&lines, true) {
+                Ok(count) => {
+                    assert!(supported);
+                    assert_eq!(count, expected);
+                }
+                Err(_) => assert!(!supported),
+            }
+            // non-diagonal lines are ignored when diagonals are not allowed
+            let expected = determine_score(make_map(read_sample(&sample), false));
+            assert_eq!(count_overlaps(&lines, false).unwrap(), expected);
+        }
+
+        let lines = read_sample("0,0 -> 4,2\n0,1 -> 4,1");
+        assert!(count_overlaps(&lines, true).is_err());
+
+        // long lines are never rasterized
+        let lines = read_sample("0,0 -> 4000000000,4000000000\n0,4000000000 -> 4000000000,0\n0,1 -> 0,3000000000\n0,2000000000 -> 0,5000000000");
+        assert_eq!(count_overlaps(&lines, true).unwrap(), 1000000003);
+    }
+}
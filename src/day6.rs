@@ -1,9 +1,48 @@
-use crate::{answer, common::Answer};
+use std::ops::{Add, Mul};
+
+use crate::{
+    answer, common,
+    common::{Answer, BigUint},
+};
 
 pub const ANSWER: Answer = answer!(5934, 26984457539);
 
 type Fish = u8;
 
+/// Timers of the lanternfish life cycle
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rules {
+    /// timer of a fish after it spawned
+    reset: u8,
+    /// timer of a newborn fish
+    spawn: u8,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules { reset: 6, spawn: 8 }
+    }
+}
+
+impl Rules {
+    /// Rules from `--reset=N` and `--spawn=N`
+    fn from_options() -> Rules {
+        let mut rules = Rules::default();
+        if let Some(reset) = common::option("reset") {
+            rules.reset = reset.parse().expect("Wrong reset timer");
+        }
+        if let Some(spawn) = common::option("spawn") {
+            rules.spawn = spawn.parse().expect("Wrong spawn timer");
+        }
+        assert!(rules.reset <= rules.spawn, "Reset timer above spawn timer");
+        rules
+    }
+
+    fn timers(&self) -> usize {
+        self.spawn as usize + 1
+    }
+}
+
 fn read_numbers(lines: Vec<String>) -> Vec<i32> {
     let line = lines.first().unwrap();
     let numbers: Vec<i32> = line.trim().split(',').map(|s| s.parse().unwrap()).collect();
@@ -18,7 +57,7 @@ fn make_fish(numbers: Vec<i32>) -> Vec<Fish> {
     fish
 }
 
-fn grow_classic(days: usize, fish: &mut Vec<Fish>) {
+fn grow_classic(days: usize, fish: &mut Vec<Fish>, rules: &Rules) {
     for day in 0..days {
         //let mut new_fish = Vec::new();
         let day_size = fish.len();
@@ -27,8 +66,8 @@ fn grow_classic(days: usize, fish: &mut Vec<Fish>) {
             //let &mut afish = &mut fish[ind];
             //println!("A fish {:?}", afish);
             if fish[ind] == 0 {
-                fish[ind] = 6;
-                fish.push(8);
+                fish[ind] = rules.reset;
+                fish.push(rules.spawn);
             } else {
                 fish[ind] -= 1;
                 //*afish = *afish - 1;
@@ -40,28 +79,26 @@ fn grow_classic(days: usize, fish: &mut Vec<Fish>) {
     }
 }
 
-fn make_counter(fish: &[Fish]) -> Vec<usize> {
-    let mut counter = vec![0; 9];
-    for d in 0..9 {
-        for afish in fish.iter() {
-            if *afish == d {
-                counter[d as usize] += 1;
-            }
-        }
+fn make_counter(fish: &[Fish], rules: &Rules) -> Vec<usize> {
+    let mut counter = vec![0; rules.timers()];
+    for afish in fish.iter() {
+        assert!(
+            *afish <= rules.spawn,
+            "Fish timer {} above spawn timer {}",
+            afish,
+            rules.spawn
+        );
+        counter[*afish as usize] += 1;
     }
     counter
 }
 
-fn grow_exp(days: usize, fish: &mut Vec<Fish>) -> usize {
-    let mut counter = make_counter(fish);
+#[allow(dead_code)]
+fn grow_exp(days: usize, fish: &mut Vec<Fish>, rules: &Rules) -> usize {
+    let mut counter = make_counter(fish, rules);
     for day in 0..days {
-        let c0 = counter[0];
-        for d in 0..6 {
-            counter[d] = counter[d + 1];
-        }
-        counter[6] = c0 + counter[7];
-        counter[7] = counter[8];
-        counter[8] = c0;
+        counter.rotate_left(1);
+        counter[rules.reset as usize] += counter[rules.spawn as usize];
 
         if false {
             // verify
@@ -76,13 +113,13 @@ fn grow_exp(days: usize, fish: &mut Vec<Fish>) -> usize {
                 day + 1,
                 fish.len(),
                 fish,
-                make_counter(fish)
+                make_counter(fish, rules)
             );
             let day_size = fish.len();
             for ind in 0..day_size {
                 if fish[ind] == 0 {
-                    fish[ind] = 6;
-                    fish.push(8);
+                    fish[ind] = rules.reset;
+                    fish.push(rules.spawn);
                 } else {
                     fish[ind] -= 1;
                 }
@@ -92,28 +129,372 @@ fn grow_exp(days: usize, fish: &mut Vec<Fish>) -> usize {
                 day + 1,
                 fish.len(),
                 fish,
-                make_counter(fish)
+                make_counter(fish, rules)
             );
             println!("\n");
-            assert!(counter == make_counter(fish));
+            assert!(counter == make_counter(fish, rules));
         }
     }
     counter.iter().sum()
 }
 
+/* Matrix projection */
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn mat_mul<T>(a: &Matrix<T>, b: &Matrix<T>, zero: &T) -> Matrix<T>
+where
+    T: Clone,
+    for<'a> &'a T: Add<&'a T, Output = T> + Mul<&'a T, Output = T>,
+{
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| (0..n).fold(zero.clone(), |acc, k| &acc + &(&a[i][k] * &b[k][j])))
+                .collect()
+        })
+        .collect()
+}
+
+/// One day maps timer counters `c` to `m * c`
+fn transition_matrix<T>(rules: &Rules, zero: &T, one: &T) -> Matrix<T>
+where
+    T: Clone,
+    for<'a> &'a T: Add<&'a T, Output = T>,
+{
+    let n = rules.timers();
+    let mut m = vec![vec![zero.clone(); n]; n];
+    for t in 0..n - 1 {
+        m[t][t + 1] = one.clone();
+    }
+    m[n - 1][0] = one.clone();
+    m[rules.reset as usize][0] = if rules.reset as usize == n - 1 {
+        // fish that reset to the spawn timer are born twice as often
+        &m[n - 1][0] + one
+    } else {
+        one.clone()
+    };
+    m
+}
+
+fn identity<T: Clone>(n: usize, zero: &T, one: &T) -> Matrix<T> {
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { one.clone() } else { zero.clone() })
+                .collect()
+        })
+        .collect()
+}
+
+/// Exact population after `days` days by repeated squaring of the transition matrix
+fn project(days: u64, counter: &[usize], rules: &Rules) -> BigUint {
+    let zero = BigUint::from_u64(0);
+    let one = BigUint::from_u64(1);
+    let mut power = identity(rules.timers(), &zero, &one);
+    let mut base = transition_matrix(rules, &zero, &one);
+    let mut exp = days;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            power = mat_mul(&power, &base, &zero);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = mat_mul(&base, &base, &zero);
+        }
+    }
+    let mut total = zero.clone();
+    for row in &power {
+        for (cell, count) in row.iter().zip(counter) {
+            total = &total + &(cell * &BigUint::from_u64(*count as u64));
+        }
+    }
+    total
+}
+
+/// Decimal logarithm of the population, for horizons whose exact count does not fit in memory
+fn project_log10(days: u64, counter: &[usize], rules: &Rules) -> f64 {
+    // matrices are kept normalized with their scale tracked separately
+    let normalize = |m: Matrix<f64>, scale: f64| {
+        let max = m.iter().flatten().fold(0.0f64, |a, b| a.max(*b));
+        let m = m
+            .iter()
+            .map(|r| r.iter().map(|v| v / max).collect())
+            .collect();
+        (m, scale + max.log10())
+    };
+    let (mut power, mut power_scale) = (identity(rules.timers(), &0.0, &1.0), 0.0);
+    let (mut base, mut base_scale) = (transition_matrix(rules, &0.0, &1.0), 0.0);
+    let mut exp = days;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            (power, power_scale) =
+                normalize(mat_mul(&power, &base, &0.0), power_scale + base_scale);
+        }
+        exp >>= 1;
+        if exp > 0 {
+            (base, base_scale) = normalize(mat_mul(&base, &base, &0.0), 2.0 * base_scale);
+        }
+    }
+    let total: f64 = power
+        .iter()
+        .flat_map(|row| row.iter().zip(counter).map(|(v, c)| v * *c as f64))
+        .sum();
+    power_scale + total.log10()
+}
+
+// exact counts are computed up to this many days, about 40000 digits
+const MAX_EXACT_DAYS: u64 = 1_000_000;
+
+/// Answer given when the population does not fit u64, it is printed instead
+const TOO_LARGE: u64 = u64::MAX;
+
+/// Leading digits of `project_log10` worth printing: rounding errors grow
+/// with the horizon to a relative error of about `days * EPSILON`
+fn trusted_digits(days: u64) -> usize {
+    (-(days as f64 * f64::EPSILON).log10())
+        .floor()
+        .clamp(0.0, 12.0) as usize
+}
+
+/// Scientific notation of `10^log` with `significant` digits, or only the
+/// digit count when no digit can be trusted
+fn format_estimate(log: f64, significant: usize) -> String {
+    let digits = log.floor() as u64 + 1;
+    if significant == 0 {
+        return format!("a number of about {} digits", digits);
+    }
+    // rounding the mantissa may carry into the exponent
+    let lead = format!("{:.*e}", significant - 1, 10f64.powf(log - log.floor()));
+    let (mantissa, carry) = lead.split_once('e').unwrap();
+    let exponent = digits - 1 + carry.parse::<u64>().unwrap();
+    format!("about {}e{} ({} digits)", mantissa, exponent, exponent + 1)
+}
+
+fn print_population(days: u64, counter: &[usize], rules: &Rules) -> u64 {
+    if days > MAX_EXACT_DAYS {
+        let log = project_log10(days, counter, rules);
+        println!(
+            "Population after {} days is {}",
+            days,
+            format_estimate(log, trusted_digits(days))
+        );
+        println!("Too large for u64, answering {}", TOO_LARGE);
+        return TOO_LARGE;
+    }
+    let population = project(days, counter, rules);
+    let digits = population.to_string();
+    if digits.len() > 60 {
+        println!(
+            "Population after {} days is {}...{} ({} digits)",
+            days,
+            &digits[..20],
+            &digits[digits.len() - 20..],
+            digits.len()
+        );
+    } else {
+        println!("Population after {} days is {}", days, digits);
+    }
+    population.to_u64().unwrap_or_else(|| {
+        println!("Too large for u64, answering {}", TOO_LARGE);
+        TOO_LARGE
+    })
+}
+
+/* Time series */
+
+/// Advance big timer counters by one day
+fn step_counter(counter: &mut [BigUint], rules: &Rules) {
+    counter.rotate_left(1);
+    counter[rules.reset as usize] = &counter[rules.reset as usize] + &counter[rules.spawn as usize];
+}
+
+/// Total population and timer buckets for each day from 0 to `days` as CSV
+fn format_series_csv(days: u64, counter: &[usize], rules: &Rules) -> String {
+    let mut counter: Vec<BigUint> = counter
+        .iter()
+        .map(|c| BigUint::from_u64(*c as u64))
+        .collect();
+    let timers: Vec<String> = (0..rules.timers())
+        .map(|t| format!("timer_{}", t))
+        .collect();
+    let mut csv = format!("day,total,{}\n", timers.join(","));
+    for day in 0..=days {
+        if day > 0 {
+            step_counter(&mut counter, rules);
+        }
+        let total = counter.iter().fold(BigUint::from_u64(0), |acc, c| &acc + c);
+        let buckets: Vec<String> = counter.iter().map(|c| c.to_string()).collect();
+        csv += &format!("{},{},{}\n", day, total, buckets.join(","));
+    }
+    csv
+}
+
+fn maybe_export_series(days: u64, counter: &[usize], rules: &Rules) {
+    if let Some(filename) = common::option("csv") {
+        std::fs::write(filename, format_series_csv(days, counter, rules))
+            .expect("Cannot export series");
+        println!("Exported {} days to {}", days, filename);
+    }
+}
+
+fn read_days(default: u64) -> u64 {
+    common::option("days").map_or(default, |d| d.parse().expect("Wrong days"))
+}
+
 pub fn process_a(lines: Vec<String>) -> u64 {
     let numbers = read_numbers(lines);
     println!("Numbers: {:?}", numbers);
+    let rules = Rules::from_options();
     let mut fish = make_fish(numbers);
     println!("Initial: {:?}", fish);
-    grow_classic(80, &mut fish);
+    let days = read_days(80);
+    maybe_export_series(days, &make_counter(&fish, &rules), &rules);
+    grow_classic(days as usize, &mut fish, &rules);
     fish.len() as u64
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
     let numbers = read_numbers(lines);
     println!("Numbers: {:?}", numbers);
-    let mut fish = make_fish(numbers);
+    let rules = Rules::from_options();
+    let fish = make_fish(numbers);
     println!("Initial: {:?}", fish);
-    grow_exp(256, &mut fish) as u64
+    let days = read_days(256);
+    let counter = make_counter(&fish, &rules);
+    maybe_export_series(days, &counter, &rules);
+    print_population(days, &counter, &rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SAMPLE: [Fish; 5] = [3, 4, 3, 1, 2];
+
+    #[test]
+    fn test_project() {
+        let rules = Rules::default();
+        let counter = make_counter(&SAMPLE, &rules);
+        assert_eq!(project(18, &counter, &rules).to_u64(), Some(26));
+        assert_eq!(project(256, &counter, &rules).to_u64(), Some(26984457539));
+
+        // population follows p(n + 9) = p(n + 2) + p(n)
+        let p = |n| project(n, &counter, &rules);
+        assert_eq!(p(3009), &p(3002) + &p(3000));
+        let log = project_log10(3000, &counter, &rules);
+        assert_eq!(log.floor() as usize + 1, p(3000).to_string().len());
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        for rules in [
+            Rules { reset: 2, spawn: 4 },
+            Rules { reset: 4, spawn: 4 },
+            Rules { reset: 0, spawn: 5 },
+        ] {
+            let fish: Vec<Fish> = SAMPLE.to_vec();
+            let counter = make_counter(&fish, &rules);
+            let mut classic = fish.clone();
+            grow_classic(40, &mut classic, &rules);
+            assert_eq!(
+                project(40, &counter, &rules).to_u64(),
+                Some(classic.len() as u64)
+            );
+            assert_eq!(grow_exp(40, &mut fish.clone(), &rules), classic.len());
+        }
+    }
+
+    #[test]
+    fn test_series_csv() {
+        let rules = Rules::default();
+        let csv = format_series_csv(3, &make_counter(&SAMPLE, &rules), &rules);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "day,total,timer_0,timer_1,timer_2,timer_3,timer_4,timer_5,timer_6,timer_7,timer_8",
+                "0,5,0,1,1,2,1,0,0,0,0",
+                "1,5,1,1,2,1,0,0,0,0,0",
+                "2,6,1,2,1,0,0,0,1,0,1",
+                "3,7,2,1,0,0,0,1,1,1,1",
+            ]
+        );
+
+        // totals agree with the fish simulation under other rules
+        let rules = Rules { reset: 2, spawn: 5 };
+        let csv = format_series_csv(30, &make_counter(&SAMPLE, &rules), &rules);
+        for (day, line) in csv.lines().skip(1).enumerate() {
+            let mut fish = SAMPLE.to_vec();
+            grow_classic(day, &mut fish, &rules);
+            assert_eq!(
+                line.split(',').nth(1),
+                Some(fish.len().to_string().as_str())
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Fish timer 6 above spawn timer 4")]
+    fn test_timer_above_spawn() {
+        make_counter(&[3, 4, 3, 1, 2, 6], &Rules { reset: 2, spawn: 4 });
+    }
+
+    #[test]
+    fn test_population_too_large() {
+        let rules = Rules::default();
+        let counter = make_counter(&SAMPLE, &rules);
+        assert_eq!(print_population(256, &counter, &rules), 26984457539);
+        assert_eq!(print_population(1000, &counter, &rules), TOO_LARGE);
+        assert_eq!(
+            print_population(MAX_EXACT_DAYS + 1, &counter, &rules),
+            TOO_LARGE
+        );
+        assert_eq!(print_population(10u64.pow(18), &counter, &rules), TOO_LARGE);
+        assert!(trusted_digits(MAX_EXACT_DAYS + 1) >= 8);
+        assert_eq!(trusted_digits(10u64.pow(18)), 0);
+    }
+
+    #[test]
+    fn test_format_estimate() {
+        let rules = Rules::default();
+        let counter = make_counter(&SAMPLE, &rules);
+        let exact = project(3000, &counter, &rules).to_string();
+        let log = project_log10(3000, &counter, &rules);
+        let estimate = format_estimate(log, 9);
+        let (mantissa, rest) = estimate["about ".len()..].split_once('e').unwrap();
+        let expected: f64 = exact[..12].parse::<f64>().unwrap() / 1e11;
+        let mantissa: f64 = mantissa.parse().unwrap();
+        assert!(
+            (mantissa - expected).abs() < 1e-7,
+            "{} vs {}",
+            estimate,
+            exact
+        );
+        assert_eq!(
+            rest,
+            format!("{} ({} digits)", exact.len() - 1, exact.len())
+        );
+
+        assert_eq!(format_estimate(2.0, 3), "about 1.00e2 (3 digits)");
+        assert_eq!(format_estimate(2.99999999, 3), "about 1.00e3 (4 digits)");
+        assert_eq!(
+            format_estimate(1.5e17, 0),
+            "a number of about 150000000000000001 digits"
+        );
+    }
+
+    #[test]
+    fn test_largest_spawn_timer() {
+        let rules = Rules {
+            reset: 3,
+            spawn: 255,
+        };
+        let fish = vec![0, 3, 255];
+        assert_eq!(make_counter(&fish, &rules)[255], 1);
+        let mut classic = fish.clone();
+        grow_classic(300, &mut classic, &rules);
+        assert_eq!(grow_exp(300, &mut fish.clone(), &rules), classic.len());
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{answer, common::Answer};
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(37, 168);
 
@@ -10,41 +10,246 @@ fn read_numbers(lines: Vec<String>) -> Vec<Pos> {
     numbers
 }
 
-type PathScore = fn(u32) -> u32;
+/// Fuel needed to move a crab over a distance, each model is convex in the distance
+#[derive(Clone, Debug, PartialEq)]
+enum CostModel {
+    Linear,
+    Triangular,
+    Quadratic,
+    /// Triangular, but a single step never costs more than the cap
+    Capped(u64),
+}
 
-fn calc_score(initial_pos: &[Pos], pos: &[Pos], path_score: PathScore) -> u32 {
-    initial_pos
-        .iter()
-        .zip(pos)
-        .map(|(a, b)| path_score((a - b).abs() as u32))
-        .sum::<u32>()
+impl CostModel {
+    fn cost(&self, distance: u32) -> u128 {
+        let d = distance as u128;
+        match self {
+            CostModel::Linear => d,
+            CostModel::Triangular => d * (d + 1) / 2,
+            CostModel::Quadratic => d * d,
+            CostModel::Capped(cap) => {
+                let cap = *cap as u128;
+                if d <= cap {
+                    d * (d + 1) / 2
+                } else {
+                    cap * (cap + 1) / 2 + (d - cap) * cap
+                }
+            }
+        }
+    }
 }
 
-fn find_best_aligned_pos(initial_pos: Vec<Pos>, path_score: PathScore) -> u64 {
-    let mut best_align_pos = 0;
-    let mut best_score: u32 = u32::max_value();
-    for align_pos in 0..initial_pos.len() {
-        let target_pos = vec![align_pos as Pos; initial_pos.len()];
-        let score = calc_score(&initial_pos, &target_pos, path_score);
-        if score < best_score {
-            best_align_pos = align_pos;
-            best_score = score;
+fn parse_cost_model(s: &str) -> CostModel {
+    match s.split_once(':') {
+        None if s == "linear" => CostModel::Linear,
+        None if s == "triangular" => CostModel::Triangular,
+        None if s == "quadratic" => CostModel::Quadratic,
+        Some(("capped", cap)) => CostModel::Capped(cap.parse().expect("Wrong cap")),
+        _ => panic!("Unknown cost model {}", s),
+    }
+}
+
+/// Cost model with optional per-crab weights
+#[derive(Clone, Debug, PartialEq)]
+struct FuelModel {
+    cost: CostModel,
+    weights: Option<Vec<u64>>,
+}
+
+impl FuelModel {
+    fn new(cost: CostModel) -> FuelModel {
+        FuelModel {
+            cost,
+            weights: None,
+        }
+    }
+
+    /// Model from `--cost=linear|triangular|quadratic|capped:N` and `--weights=w1,w2,..`
+    fn from_options(default_cost: CostModel) -> FuelModel {
+        let mut model = FuelModel::new(default_cost);
+        if let Some(cost) = common::option("cost") {
+            model.cost = parse_cost_model(cost);
+        }
+        if let Some(weights) = common::option("weights") {
+            model.weights = Some(
+                weights
+                    .split(',')
+                    .map(|w| w.trim().parse().expect("Wrong weight"))
+                    .collect(),
+            );
         }
-        println!("For pos {:?} score={}", target_pos, score);
+        model
     }
-    println!("Best align pos is {}", best_align_pos);
-    best_score as u64
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
+#[derive(Debug, PartialEq)]
+struct Alignment {
+    pos: Pos,
+    fuel: u64,
+}
+
+fn calc_score(initial_pos: &[Pos], align_pos: Pos, model: &FuelModel) -> u64 {
+    let total: u128 = match &model.weights {
+        None => initial_pos
+            .iter()
+            .map(|p| model.cost.cost(p.abs_diff(align_pos)))
+            .sum(),
+        Some(weights) => initial_pos
+            .iter()
+            .zip(weights)
+            .map(|(p, w)| model.cost.cost(p.abs_diff(align_pos)) * *w as u128)
+            .sum(),
+    };
+    u64::try_from(total).expect("Fuel overflows u64")
+}
+
+fn position_range(initial_pos: &[Pos]) -> (Pos, Pos) {
+    let min = *initial_pos.iter().min().unwrap();
+    let max = *initial_pos.iter().max().unwrap();
+    (min, max)
+}
+
+/// Leftmost minimum of a cost convex in the position, by binary search on its slope
+fn convex_search(initial_pos: &[Pos], lo: Pos, hi: Pos, model: &FuelModel) -> Alignment {
+    let (mut lo, mut hi) = (lo, hi);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if calc_score(initial_pos, mid, model) <= calc_score(initial_pos, mid + 1, model) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Alignment {
+        pos: lo,
+        fuel: calc_score(initial_pos, lo, model),
+    }
+}
+
+/// Linear cost is minimized at the median
+fn align_linear(initial_pos: &[Pos]) -> Alignment {
+    let mut sorted = initial_pos.to_vec();
+    sorted.sort_unstable();
+    let pos = sorted[(sorted.len() - 1) / 2];
+    Alignment {
+        pos,
+        fuel: calc_score(initial_pos, pos, &FuelModel::new(CostModel::Linear)),
+    }
+}
+
+/// Triangular cost is minimized within 1/2 of the mean
+fn align_triangular(initial_pos: &[Pos]) -> Alignment {
+    let (min, max) = position_range(initial_pos);
+    let sum: i64 = initial_pos.iter().map(|p| *p as i64).sum();
+    let mean = sum.div_euclid(initial_pos.len() as i64) as Pos;
+    let lo = (mean - 1).max(min);
+    let hi = (mean + 2).min(max);
+    convex_search(initial_pos, lo, hi, &FuelModel::new(CostModel::Triangular))
+}
+
+fn find_best_aligned_pos(initial_pos: &[Pos], model: &FuelModel) -> Alignment {
+    if let Some(weights) = &model.weights {
+        assert_eq!(weights.len(), initial_pos.len(), "Need one weight per crab");
+    }
+    match model {
+        FuelModel {
+            cost: CostModel::Linear,
+            weights: None,
+        } => align_linear(initial_pos),
+        FuelModel {
+            cost: CostModel::Triangular,
+            weights: None,
+        } => align_triangular(initial_pos),
+        _ => {
+            let (min, max) = position_range(initial_pos);
+            convex_search(initial_pos, min, max, model)
+        }
+    }
+}
+
+fn solve(lines: Vec<String>, default_cost: CostModel) -> u64 {
     let pos = read_numbers(lines);
+    let model = FuelModel::from_options(default_cost);
+
+    let best = find_best_aligned_pos(&pos, &model);
+    println!(
+        "Best align pos is {} with fuel {} ({:?})",
+        best.pos, best.fuel, model.cost
+    );
+    best.fuel
+}
 
-    find_best_aligned_pos(pos, |x| x)
+pub fn process_a(lines: Vec<String>) -> u64 {
+    solve(lines, CostModel::Linear)
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let pos = read_numbers(lines);
-    let path_score_b = |n| (1..n + 1).sum::<u32>();
+    solve(lines, CostModel::Triangular)
+}
 
-    find_best_aligned_pos(pos, path_score_b)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Try every position between the leftmost and rightmost crab
+    fn find_best_aligned_pos_exhaustive(initial_pos: &[Pos], model: &FuelModel) -> Alignment {
+        let (min, max) = position_range(initial_pos);
+        (min..=max)
+            .map(|pos| Alignment {
+                pos,
+                fuel: calc_score(initial_pos, pos, model),
+            })
+            .min_by_key(|a| (a.fuel, a.pos))
+            .unwrap()
+    }
+
+    static SAMPLE: [Pos; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+
+    #[test]
+    fn test_sample() {
+        assert_eq!(align_linear(&SAMPLE), Alignment { pos: 2, fuel: 37 });
+        assert_eq!(align_triangular(&SAMPLE), Alignment { pos: 5, fuel: 168 });
+    }
+
+    #[test]
+    fn test_cost_models() {
+        assert_eq!(parse_cost_model("capped:3"), CostModel::Capped(3));
+        let costs: Vec<u128> = (0..6).map(|d| CostModel::Capped(3).cost(d)).collect();
+        assert_eq!(costs, vec![0, 1, 3, 6, 9, 12]);
+        assert_eq!(
+            CostModel::Quadratic.cost(u32::MAX),
+            (u32::MAX as u128).pow(2)
+        );
+
+        let weighted = FuelModel {
+            cost: CostModel::Linear,
+            weights: Some(vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 100]),
+        };
+        assert_eq!(find_best_aligned_pos(&SAMPLE, &weighted).pos, 14);
+    }
+
+    #[test]
+    fn test_against_exhaustive() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let models = [
+            CostModel::Linear,
+            CostModel::Triangular,
+            CostModel::Quadratic,
+            CostModel::Capped(10),
+        ];
+        for _ in 0..100 {
+            let n = rng.gen_range(1..30);
+            let pos: Vec<Pos> = (0..n).map(|_| rng.gen_range(-50..2000)).collect();
+            for cost in &models {
+                let mut model = FuelModel::new(cost.clone());
+                if rng.gen_bool(0.5) {
+                    model.weights = Some((0..n).map(|_| rng.gen_range(0..10)).collect());
+                }
+                let expected = find_best_aligned_pos_exhaustive(&pos, &model);
+                assert_eq!(find_best_aligned_pos(&pos, &model).fuel, expected.fuel);
+            }
+        }
+    }
 }
@@ -1,208 +1,289 @@
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 
-use crate::{answer, common::Answer};
+use anyhow::{anyhow, bail};
+
+use crate::{answer, common, common::Answer};
 
 pub const ANSWER: Answer = answer!(26, 61229);
 
 /* Impl */
 
-fn is_unique(s: &str) -> bool {
-    s.len() == 2 || s.len() == 3 || s.len() == 4 || s.len() == 7
-}
+// segments of digits 0 to 9 on a seven-segment display
+const STANDARD_TABLE: &str = "abcefg,cf,acdeg,acdfg,bcdf,abdfg,abdefg,acf,abcdefg,abcdfg";
 
-fn find_digit(digits: &[&str], count: usize) -> Vec<char> {
-    if let Some(v) = digits.iter().find(|d| d.len() == count) {
-        v.chars().collect::<Vec<char>>()
-    } else {
-        panic!()
-    }
+/// Segments lit for each digit, a digit value is its index
+struct DigitTable {
+    segments: Vec<char>,
+    digits: Vec<u32>,
 }
 
-fn subset(larger: &[char], smaller: &[char]) -> Vec<char> {
-    let mut result: Vec<char> = vec![];
-    for l in larger {
-        if !smaller.contains(l) {
-            result.push(*l);
+impl DigitTable {
+    /// Table from comma-separated segment patterns of digits 0, 1, ..
+    fn parse(spec: &str) -> anyhow::Result<DigitTable> {
+        let segments: Vec<char> = spec
+            .chars()
+            .filter(|c| *c != ',')
+            .collect::<BTreeSet<char>>()
+            .into_iter()
+            .collect();
+        if segments.len() > u32::BITS as usize {
+            bail!("too many segments in {}", spec);
         }
+        let mut table = DigitTable {
+            segments,
+            digits: vec![],
+        };
+        for pattern in spec.split(',') {
+            let digit = table.pattern(pattern)?;
+            if digit == 0 || table.digits.contains(&digit) {
+                bail!("empty or repeated digit pattern {:?}", pattern);
+            }
+            table.digits.push(digit);
+        }
+        if table.digits.len() < 2 {
+            bail!("need at least two digits in {}", spec);
+        }
+        Ok(table)
     }
-    result
-}
 
-fn find_mapping(input: &[&str]) -> HashMap<char, char> {
-    println!("In {:?}", input);
-    // wire to real digit
-    let mut mapping: HashMap<char, char> = HashMap::new();
-    let dig1 = find_digit(input, 2);
-    let dig7 = find_digit(input, 3);
-    let dig4 = find_digit(input, 4);
-    let dig8 = find_digit(input, 7);
-    println!("1={:?}, 7={:?} 4={:?} 8={:?}", dig1, dig7, dig4, dig8);
-
-    // a must be 8 times
-    // b must be 6 times
-    // c must be 8 times
-    // d must be 7 times
-    // e must be 4 times
-    // f must be 9 times
-    // g must be 7 times
-    let frequences: HashMap<char, usize> = HashMap::from_iter(
-        "abcdefg"
-            .chars()
-            .map(|c| (c, input.iter().filter(|one| one.contains(c)).count())),
-    );
-    println!("Frequences: {:?}", frequences);
-
-    // Handle digit 1
-    let remaining1 = &dig1;
-    println!("for 1 {:?}", remaining1);
-
-    let count_c = *frequences.get(&remaining1[0]).unwrap();
-    let count_f = *frequences.get(&remaining1[1]).unwrap();
-    // c must be 8 times
-    // f must be 9 times
-    if count_c == 8 && count_f == 9 {
-        mapping.insert(remaining1[0], 'c');
-        mapping.insert(remaining1[1], 'f');
-    } else if count_f == 8 && count_c == 9 {
-        mapping.insert(remaining1[1], 'c');
-        mapping.insert(remaining1[0], 'f');
-    } else {
-        panic!();
+    /// Table from `--table=pattern0,pattern1,..`, seven-segment digits by default
+    fn from_options() -> DigitTable {
+        DigitTable::parse(common::option("table").unwrap_or(STANDARD_TABLE))
+            .unwrap_or_else(|e| panic!("Wrong digit table: {}", e))
     }
 
-    // Handle digit 7
-    let remaining7 = subset(&dig7, &dig1);
-    println!("for 7 {:?}", remaining7);
-
-    mapping.insert(remaining7[0], 'a');
-
-    println!("Mapping so far {:?}", mapping);
-    let remaining4 = subset(&dig4, &dig1);
-    println!("for 4 {:?}", remaining4);
-
-    let count_b = *frequences.get(&remaining4[0]).unwrap();
-    let count_d = *frequences.get(&remaining4[1]).unwrap();
-    println!("countb {}, countd {}", count_b, count_d);
-    if count_b == 6 && count_d == 7 {
-        // b must be 6 times
-        // d must be 7 times
-        mapping.insert(remaining4[0], 'b');
-        mapping.insert(remaining4[1], 'd');
-    } else if count_d == 6 && count_b == 7 {
-        mapping.insert(remaining4[1], 'b');
-        mapping.insert(remaining4[0], 'd');
-    } else {
-        panic!();
+    fn pattern(&self, s: &str) -> anyhow::Result<u32> {
+        s.chars().try_fold(0u32, |mask, c| {
+            let ind = self
+                .segments
+                .iter()
+                .position(|seg| *seg == c)
+                .ok_or_else(|| anyhow!("unknown wire {:?} in {:?}", c, s))?;
+            Ok(mask | 1 << ind)
+        })
     }
 
-    // Handle digit 9
-    let existing: Vec<char> = mapping.keys().copied().collect();
-    let remaining9 = subset(
-        "abcdefg".chars().collect::<Vec<char>>().as_slice(),
-        &existing,
-    );
-    println!("For 9 {:?}", remaining9);
-    let count_g = *frequences.get(&remaining9[0]).unwrap();
-    let count_e = *frequences.get(&remaining9[1]).unwrap();
-    println!("count_g {}, count_e {}", count_g, count_e);
-    if count_g == 7 && count_e == 4 {
-        // g must be 7 times
-        // e must be 4 times
-        mapping.insert(remaining9[0], 'g');
-        mapping.insert(remaining9[1], 'e');
-    } else if count_e == 7 && count_g == 4 {
-        mapping.insert(remaining9[1], 'g');
-        mapping.insert(remaining9[0], 'e');
-    } else {
-        panic!();
+    fn digit(&self, segments: u32) -> Option<u64> {
+        self.digits
+            .iter()
+            .position(|d| *d == segments)
+            .map(|d| d as u64)
     }
 
-    println!("Mapping {:?}", mapping);
-    assert_eq!(mapping.len(), 7);
-    mapping
+    fn with_count(&self, count: u32) -> impl Iterator<Item = &u32> {
+        self.digits.iter().filter(move |d| d.count_ones() == count)
+    }
+
+    fn is_unique(&self, s: &str) -> bool {
+        self.with_count(s.len() as u32).count() == 1
+    }
 }
 
-fn decode(mapping: &HashMap<char, char>, digits: &[&str]) -> u64 {
-    let patterns: HashMap<Vec<char>, u64> = HashMap::from([
-        ("abcefg".chars().collect(), 0),
-        ("cf".chars().collect(), 1),
-        ("acdeg".chars().collect(), 2),
-        ("acdfg".chars().collect(), 3),
-        ("bcdf".chars().collect(), 4),
-        ("abdfg".chars().collect(), 5),
-        ("abdefg".chars().collect(), 6),
-        ("acf".chars().collect(), 7),
-        ("abcdefg".chars().collect(), 8),
-        ("abcdfg".chars().collect(), 9),
-    ]);
-
-    println!(" digits {:?}", digits);
-    let decoded_digits: Vec<u64> = digits
-        .iter()
-        .map(|digit| {
-            let mut mapped: Vec<char> = digit
-                .chars()
-                .map(|c| mapping.get(&c).unwrap())
-                .copied()
-                .collect();
-            mapped.sort_unstable();
-            *patterns.get(&mapped).unwrap()
-        })
-        .collect();
-    let decoded_number: u64 = decoded_digits
+/// Segment index for each wire index
+type Mapping = Vec<usize>;
+
+fn map_pattern(mapping: &Mapping, wires: u32) -> u32 {
+    mapping
         .iter()
         .enumerate()
-        .map(|(i, v)| v * 10u64.pow(3u32 - (i as u32)))
-        .sum::<u64>();
-    decoded_number
+        .filter(|(wire, _)| wires & 1 << wire != 0)
+        .fold(0, |mask, (_, seg)| mask | 1 << seg)
 }
 
-pub fn process_a(lines: Vec<String>) -> u64 {
-    let outs: Vec<Vec<&str>> = lines
+/// Segments each wire can drive given the lengths of the observed patterns
+fn propagate(table: &DigitTable, observed: &[u32]) -> Vec<u32> {
+    let all: u32 = u32::MAX >> (u32::BITS as usize - table.segments.len());
+    let mut candidates = vec![all; table.segments.len()];
+    for pattern in observed {
+        let (lit, unlit) = table
+            .with_count(pattern.count_ones())
+            .fold((0, 0), |(lit, unlit), d| (lit | d, unlit | (!d & all)));
+        for (wire, cand) in candidates.iter_mut().enumerate() {
+            *cand &= if pattern & 1 << wire != 0 { lit } else { unlit };
+        }
+    }
+    candidates
+}
+
+/// All wirings turning every observed pattern into a digit of the table
+fn find_mappings(table: &DigitTable, observed: &[u32]) -> Vec<Mapping> {
+    fn search(
+        table: &DigitTable,
+        observed: &[u32],
+        candidates: &[u32],
+        order: &[usize],
+        mapping: &mut Mapping,
+        used: u32,
+        found: &mut Vec<Mapping>,
+    ) {
+        let Some((&wire, rest)) = order.split_first() else {
+            if observed
+                .iter()
+                .all(|p| table.digit(map_pattern(mapping, *p)).is_some())
+            {
+                found.push(mapping.clone());
+            }
+            return;
+        };
+        for seg in 0..table.segments.len() {
+            if candidates[wire] & 1 << seg != 0 && used & 1 << seg == 0 {
+                mapping[wire] = seg;
+                search(
+                    table,
+                    observed,
+                    candidates,
+                    rest,
+                    mapping,
+                    used | 1 << seg,
+                    found,
+                );
+            }
+        }
+    }
+
+    let candidates = propagate(table, observed);
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by_key(|wire| candidates[*wire].count_ones());
+    let mut found = vec![];
+    let mut mapping = vec![0; candidates.len()];
+    search(
+        table,
+        observed,
+        &candidates,
+        &order,
+        &mut mapping,
+        0,
+        &mut found,
+    );
+    found
+}
+
+/// Output number of a display, an error when no wiring or several readings fit
+fn decode(table: &DigitTable, inputs: &[&str], outputs: &[&str]) -> anyhow::Result<u64> {
+    let inputs: Vec<u32> = inputs
+        .iter()
+        .map(|s| table.pattern(s))
+        .collect::<anyhow::Result<_>>()?;
+    let outputs: Vec<u32> = outputs
+        .iter()
+        .map(|s| table.pattern(s))
+        .collect::<anyhow::Result<_>>()?;
+    let observed: Vec<u32> = inputs.iter().chain(&outputs).copied().collect();
+
+    let mappings = find_mappings(table, &observed);
+    let readings: BTreeSet<Vec<u64>> = mappings
         .iter()
-        .map(|s| {
-            s.split_once('|')
-                .unwrap()
-                .1
-                .split_whitespace()
-                .collect::<Vec<&str>>()
+        .map(|m| {
+            outputs
+                .iter()
+                .map(|p| table.digit(map_pattern(m, *p)).unwrap())
+                .collect()
         })
         .collect();
+    let digits = match readings.len() {
+        0 => bail!("impossible display, no wiring matches the digit table"),
+        1 => readings.into_iter().next().unwrap(),
+        _ => bail!("ambiguous display, outputs could read {:?}", readings),
+    };
+    let radix = table.digits.len() as u64;
+    digits.iter().try_fold(0u64, |number, d| {
+        number
+            .checked_mul(radix)
+            .and_then(|n| n.checked_add(*d))
+            .ok_or_else(|| anyhow!("output {:?} does not fit u64", digits))
+    })
+}
+
+fn split_entry(line: &str) -> (Vec<&str>, Vec<&str>) {
+    let (ins, outs) = line.split_once('|').expect("Missing |");
+    (
+        ins.split_whitespace().collect(),
+        outs.split_whitespace().collect(),
+    )
+}
+
+pub fn process_a(lines: Vec<String>) -> u64 {
+    let table = DigitTable::from_options();
+    let outs: Vec<Vec<&str>> = lines.iter().map(|s| split_entry(s).1).collect();
     println!("Outs {:?}", outs);
     outs.iter()
-        .map(|digits| digits.iter().filter(|d| is_unique(d)).count())
+        .map(|digits| digits.iter().filter(|d| table.is_unique(d)).count())
         .sum::<usize>() as u64
 }
 
 pub fn process_b(lines: Vec<String>) -> u64 {
-    let ins: Vec<Vec<&str>> = lines
-        .iter()
-        .map(|s| {
-            s.split_once('|')
-                .unwrap()
-                .0
-                .split_whitespace()
-                .collect::<Vec<&str>>()
-        })
-        .collect();
-    let outs: Vec<Vec<&str>> = lines
+    let table = DigitTable::from_options();
+    let decoded_numbers: Vec<u64> = lines
         .iter()
-        .map(|s| {
-            s.split_once('|')
-                .unwrap()
-                .1
-                .split_whitespace()
-                .collect::<Vec<&str>>()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(ind, line)| {
+            let (ins, outs) = split_entry(line);
+            decode(&table, &ins, &outs).unwrap_or_else(|e| panic!("line {}: {}", ind + 1, e))
         })
         .collect();
-    println!("Ins {:?}", ins);
-    println!("Outs {:?}", outs);
-
-    let decoded_numbers: Vec<u64> = ins
-        .iter()
-        .zip(outs.iter())
-        .map(|(inp, digits)| decode(&find_mapping(inp), digits))
-        .collect();
     println!("{:?}", decoded_numbers);
     decoded_numbers.iter().sum()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_line(table: &DigitTable, line: &str) -> anyhow::Result<u64> {
+        let (ins, outs) = split_entry(line);
+        decode(table, &ins, &outs)
+    }
+
+    #[test]
+    fn test_decode() {
+        let table = DigitTable::parse(STANDARD_TABLE).unwrap();
+        let line =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf";
+        assert_eq!(decode_line(&table, line).unwrap(), 5353);
+        // outputs alone pin the wiring when they are distinctive enough
+        let line = "| ab dab eafb acedgfb cdfbe cagedb";
+        assert_eq!(decode_line(&table, line).unwrap(), 174_850);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let table = DigitTable::parse(STANDARD_TABLE).unwrap();
+        let error = |line| decode_line(&table, line).unwrap_err().to_string();
+        assert_eq!(
+            error("ab ac | ab"),
+            "impossible display, no wiring matches the digit table"
+        );
+        assert_eq!(
+            error("abcde | abcde"),
+            "ambiguous display, outputs could read {[2], [3], [5]}"
+        );
+        assert_eq!(error("ab | xy"), "unknown wire 'x' in \"xy\"");
+    }
+
+    #[test]
+    fn test_custom_table() {
+        let table = DigitTable::parse("a,ab,abc").unwrap();
+        assert_eq!(decode_line(&table, "c ca | cab c c").unwrap(), 18);
+        assert!(table.is_unique("ab"));
+        assert!(DigitTable::parse("ab,ba").is_err());
+    }
+
+    #[test]
+    fn test_full_width_table() {
+        // digit k lights the first k + 1 of 32 segments
+        let wires: Vec<char> = ('A'..='Z').chain('a'..='f').collect();
+        let digit = |wires: &[char], k: usize| wires[..=k].iter().collect::<String>();
+        let spec: Vec<String> = (0..32).map(|k| digit(&wires, k)).collect();
+        let table = DigitTable::parse(&spec.join(",")).unwrap();
+        assert_eq!(table.segments.len(), 32);
+
+        let scrambled: Vec<char> = wires.iter().rev().copied().collect();
+        let inputs: Vec<String> = (0..32).map(|k| digit(&scrambled, k)).collect();
+        let outputs: Vec<String> = [31, 0, 5].iter().map(|k| digit(&scrambled, *k)).collect();
+        let line = format!("{} | {}", inputs.join(" "), outputs.join(" "));
+        assert_eq!(decode_line(&table, &line).unwrap(), 31 * 32 * 32 + 5);
+        let extra = spec.join(",") + ",0";
+        assert!(DigitTable::parse(&extra).is_err());
+    }
+}
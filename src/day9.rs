@@ -1,4 +1,6 @@
-use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use colored::*;
 
 use crate::{answer, common::Answer};
 
@@ -78,66 +80,107 @@ fn process_board(board: Board) -> u64 {
     risk
 }
 
-// Breadth-first search
-struct Search<'a> {
-    board: &'a Board,
-    visited: HashSet<Point>,
+/* Basins */
+
+/// Connected area of cells lower than 9
+#[derive(Debug, PartialEq)]
+struct Basin {
+    size: usize,
+    /// lowest cell, the first one in reading order on a plateau
+    low: Point,
+    /// bounding box corners
+    top_left: Point,
+    bottom_right: Point,
 }
 
-impl<'a> Search<'a> {
-    fn new(board: &'a Board) -> Search<'a> {
-        Search {
-            board,
-            visited: HashSet::new(),
-        }
-    }
+/// Basin ID of every cell, `None` for height 9
+type Labels = Vec<Vec<Option<usize>>>;
 
-    fn grow(&mut self, point: Point) {
-        self.visited.insert(point);
-        // check neighbours
-        //let adjacents: Vec<Point> = find_adjacent_points(&self.board, point.0, point.1);
-        let adjacents: Vec<Point> = find_adjacent_points(&self.board, point.0, point.1)
-            .into_iter()
-            .filter(|p| !self.visited.contains(p))
-            .filter(|p| self.board.points[p.0][p.1] < 9)
-            .collect();
-        if !adjacents.is_empty() {
-            println!("Found {} adjacents for {:?}", adjacents.len(), point);
-        }
-        for adj in adjacents {
-            self.grow(adj);
+/// Label all basins in a single pass with an iterative flood fill
+fn label_basins(board: &Board) -> (Labels, Vec<Basin>) {
+    let mut labels: Labels = vec![vec![None; board.cols]; board.rows];
+    let mut basins: Vec<Basin> = Vec::new();
+    let mut queue: VecDeque<Point> = VecDeque::new();
+    for row in 0..board.rows {
+        for col in 0..board.cols {
+            if board.points[row][col] >= 9 || labels[row][col].is_some() {
+                continue;
+            }
+            let id = basins.len();
+            let mut basin = Basin {
+                size: 0,
+                low: (row, col),
+                top_left: (row, col),
+                bottom_right: (row, col),
+            };
+            labels[row][col] = Some(id);
+            queue.push_back((row, col));
+            while let Some(point) = queue.pop_front() {
+                basin.size += 1;
+                let (low_height, height) = (
+                    board.points[basin.low.0][basin.low.1],
+                    board.points[point.0][point.1],
+                );
+                if height < low_height || (height == low_height && point < basin.low) {
+                    basin.low = point;
+                }
+                basin.top_left = (basin.top_left.0.min(point.0), basin.top_left.1.min(point.1));
+                basin.bottom_right = (
+                    basin.bottom_right.0.max(point.0),
+                    basin.bottom_right.1.max(point.1),
+                );
+                for adj in find_adjacent_points(board, point.0, point.1) {
+                    if board.points[adj.0][adj.1] < 9 && labels[adj.0][adj.1].is_none() {
+                        labels[adj.0][adj.1] = Some(id);
+                        queue.push_back(adj);
+                    }
+                }
+            }
+            basins.push(basin);
         }
     }
-
-    fn locate(&mut self, base: Point) -> Vec<Point> {
-        self.grow(base);
-        self.visited.drain().collect()
-    }
+    (labels, basins)
 }
 
-fn locate_basin(board: &Board, row: usize, col: usize) -> u32 {
-    let low: Point = (row, col);
-    let mut search = Search::new(board);
-    let points = search.locate(low);
-    points.len() as u32
+const PALETTE: [Color; 6] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+];
+
+/// Heights coloured by basin, low points in bold
+fn print_labels(board: &Board, labels: &Labels, basins: &[Basin]) {
+    for (row, (heights, row_labels)) in board.points.iter().zip(labels).enumerate() {
+        for (col, (height, label)) in heights.iter().zip(row_labels).enumerate() {
+            let height = height.to_string();
+            match label {
+                None => print!("{}", height.dimmed()),
+                Some(id) if basins[*id].low == (row, col) => {
+                    print!("{}", height.color(PALETTE[id % PALETTE.len()]).bold())
+                }
+                Some(id) => print!("{}", height.color(PALETTE[id % PALETTE.len()])),
+            }
+        }
+        println!();
+    }
 }
 
 fn process_basins(board: Board) -> u64 {
-    let mut basins: Vec<u64> = Vec::new();
-    for row in 0..board.rows {
-        for col in 0..board.cols {
-            if is_low(&board, row, col) {
-                // find basin around point, it is a basin size
-                let basin = locate_basin(&board, row, col);
-                println!("Located basin {} around {}:{}", basin, row, col);
-                basins.push(basin as u64);
-            }
-        }
+    let (labels, basins) = label_basins(&board);
+    print_labels(&board, &labels, &basins);
+    for (id, basin) in basins.iter().enumerate() {
+        println!(
+            "Basin {} of size {} low at {:?}, within {:?}..{:?}",
+            id, basin.size, basin.low, basin.top_left, basin.bottom_right
+        );
     }
-    println!("Basins {:?}", basins);
-    basins.sort_by(|a, b| a.cmp(b).reverse());
-    assert!(basins.len() >= 3);
-    basins[0..3].iter().product()
+    let mut sizes: Vec<u64> = basins.iter().map(|b| b.size as u64).collect();
+    sizes.sort_by(|a, b| a.cmp(b).reverse());
+    assert!(sizes.len() >= 3);
+    sizes[0..3].iter().product()
 }
 
 pub fn process_a(lines: Vec<String>) -> u64 {
@@ -149,3 +192,47 @@ pub fn process_b(lines: Vec<String>) -> u64 {
     let board = make_board(lines);
     process_basins(board)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_lines(sample: &str) -> Vec<String> {
+        sample.lines().map(String::from).collect()
+    }
+
+    static SAMPLE: &str = "2199943210
+3987894921
+9856789892
+8767896789
+9899965678";
+
+    #[test]
+    fn test_label_sample() {
+        let (labels, basins) = label_basins(&make_board(to_lines(SAMPLE)));
+        let sizes: Vec<usize> = basins.iter().map(|b| b.size).collect();
+        assert_eq!(sizes, vec![3, 9, 14, 9]);
+        let lows: Vec<Point> = basins.iter().map(|b| b.low).collect();
+        assert_eq!(lows, vec![(0, 1), (0, 9), (2, 2), (4, 6)]);
+        assert_eq!(
+            (basins[2].top_left, basins[2].bottom_right),
+            ((1, 0), (4, 5))
+        );
+        assert_eq!(labels[0][2], None);
+        assert_eq!(labels[3][0], Some(2));
+    }
+
+    #[test]
+    fn test_label_plateau() {
+        let board = make_board(to_lines("99999\n95559\n99999"));
+        assert!(!(0..5).any(|col| is_low(&board, 1, col)));
+        let (_, basins) = label_basins(&board);
+        assert_eq!(basins.len(), 1);
+        assert_eq!(basins[0].low, (1, 1));
+
+        // a single large basin would overflow a recursive search
+        let lines: Vec<String> = (0..400).map(|_| "1".repeat(400)).collect();
+        let (_, basins) = label_basins(&make_board(lines));
+        assert_eq!(basins[0].size, 160000);
+    }
+}
@@ -113,6 +113,9 @@ macro_rules! days {
     };
 }
 
+/// Days reading stdin line by line with `--stream`
+const STREAMING_DAYS: [usize; 1] = [1];
+
 /// Collect all days from crate
 fn init_registry() -> anyhow::Result<Registry> {
     let mut registry: Registry = HashMap::new();
@@ -126,11 +129,37 @@ fn init_registry() -> anyhow::Result<Registry> {
     Ok(registry)
 }
 
+/// Split arguments into positional ones and `--name[=value]` options
+fn parse_args() -> (Vec<String>, HashMap<String, String>) {
+    let mut positional: Vec<String> = Vec::new();
+    let mut options: HashMap<String, String> = HashMap::new();
+    for arg in env::args().skip(1) {
+        if let Some(option) = arg.strip_prefix("--") {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            options.insert(name.to_string(), value.to_string());
+        } else {
+            positional.push(arg);
+        }
+    }
+    (positional, options)
+}
+
 fn main() -> anyhow::Result<()> {
-    let day: usize = env::args().nth(1).expect("provide day").parse()?;
+    let (args, options) = parse_args();
+    common::set_options(options);
+    let day: usize = args.first().expect("provide day").parse()?;
     println!("{}", format!("Run day {}", day).blue());
-    let lines: Vec<String> = match env::args().nth(3) {
-        Some(filename) => read_lines_from_file(filename)?,
+    let streaming = common::option("stream").is_some();
+    if streaming && !STREAMING_DAYS.contains(&day) {
+        return Err(anyhow!("day {} does not support --stream", day));
+    }
+    if streaming && args.get(2).is_some() {
+        return Err(anyhow!("--stream reads stdin, do not pass a filename"));
+    }
+    let lines: Vec<String> = match args.get(2) {
+        Some(filename) => read_lines_from_file(filename.clone())?,
+        // streaming days consume stdin themselves
+        None if streaming => vec![],
         None => {
             if !atty::is(Stream::Stdin) {
                 read_lines_stdin()?
@@ -139,8 +168,8 @@ fn main() -> anyhow::Result<()> {
             }
         }
     };
-    let task_type: TaskType = match env::args()
-        .nth(2)
+    let task_type: TaskType = match args
+        .get(1)
         .context("Expected a or b as second argument")?
         .as_str()
     {